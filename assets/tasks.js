@@ -0,0 +1,11 @@
+// Make task list checkboxes clickable and remember their state per page
+document.querySelectorAll(".task-list-item-checkbox").forEach((checkbox, idx) => {
+  const key = `bookmark:${location.pathname}:task-${idx}`;
+  const saved = localStorage.getItem(key);
+
+  if (saved !== null) checkbox.checked = saved === "true";
+  checkbox.disabled = false;
+  checkbox.addEventListener("change", () => {
+    localStorage.setItem(key, checkbox.checked);
+  });
+});
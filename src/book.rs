@@ -1,18 +1,23 @@
 use crate::config::{Config, Page};
+use crate::diagrams::SpecialLanguage;
 use crate::documents::Document;
-use crate::utils::{md_to_html, copy_recursively};
+use crate::highlight::Highlighter;
+use crate::utils::{copy_recursively, escape_html, md_to_html};
 use inquire::{validator::Validation, Text};
+use std::collections::BTreeSet;
+use std::sync::Arc;
 use std::thread;
 use std::{fs, path::Path};
 
 pub struct Book;
 
 impl Book {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(name: &str, force: bool) {
         let path = Path::new(name);
-        if path.exists() && force == false {
+        if path.exists() && !force {
             panic!("`{}` already exist", name)
-        } else if path.exists() && force == true {
+        } else if path.exists() && force {
             fs::remove_dir_all(path).unwrap();
         }
 
@@ -45,7 +50,7 @@ impl Book {
             panic!("Missing book.json")
         }
 
-        let config = Config::from_file(config_path);
+        let config = Arc::new(Config::from_file(config_path));
 
         let dist = Path::new(&config.dist_dir);
 
@@ -54,38 +59,89 @@ impl Book {
         }
 
         fs::create_dir(dist).unwrap();
-        Book::move_assets(&config.dist_dir,  &config.assets_dir);
-        let sidebar = Book::make_sidebar(&config.pages, &config.bookname);
+        let highlighter = Arc::new(Highlighter::new(&config));
+        let css = format!("{}\n{}", highlighter.css(), Book::admonition_styles(&config));
+        Book::move_assets(&config.dist_dir,  &config.assets_dir, &css);
 
         let mut handlers = vec![];
-        for page in config.pages {
-            let root = config.root_dir.clone();
-            let dist = config.dist_dir.clone();
-            let sidebar = sidebar.clone();
+        for page in &config.pages {
+            let page = page.clone();
+            let config = Arc::clone(&config);
+            let highlighter = Arc::clone(&highlighter);
             let handler = thread::Builder::new()
                 .name(page.title.clone())
                 .spawn(move || {
-                    let file = format!("./{}/{}", root, page.path);
+                    let file = format!("./{}/{}", config.root_dir, page.path);
                     let path = Path::new(&file);
-                    let doc = Document::from_file(path);
-                    let output_path = md_to_html(&format!("./{}/{}", dist, page.path));
-                    doc.save(&output_path, &sidebar);
+                    let doc = Document::from_file(path, &config, &highlighter);
 
-                    page.title
+                    (page, doc)
                 })
                 .unwrap();
 
             handlers.push(handler);
         }
 
+        let mut documents = vec![];
+        let mut languages = BTreeSet::new();
         for handler in handlers {
-            let page = handler.join().unwrap();
-            println!("[Done] {}", page);
+            let (page, doc) = handler.join().unwrap();
+            for diagnostic in &doc.diagnostics {
+                println!("[Warning] {}:{}: {}", page.path, diagnostic.line, diagnostic.message);
+            }
+            if doc.front_matter.draft {
+                println!("[Draft] {}", doc.title(&page));
+                continue;
+            }
+            languages.extend(doc.languages.iter().copied());
+            documents.push((page, doc));
+        }
+
+        // Pages are parsed first as their front matter can rename them in the sidebar
+        let pages = documents
+            .iter()
+            .map(|(page, doc)| Page {
+                title: doc.title(page).to_string(),
+                path: page.path.clone(),
+            })
+            .collect::<Vec<Page>>();
+        let sidebar = Book::make_sidebar(&pages, &config.bookname);
+        let missing = Book::move_vendor_scripts(&config, &languages);
+
+        for (page, (_, doc)) in pages.iter().zip(&mut documents) {
+            // A page can't load a script that wasn't provided, its blocks stay plain text
+            doc.languages.retain(|name| !missing.contains(name));
+            let output_path = md_to_html(&format!("./{}/{}", config.dist_dir, page.path));
+            doc.save(&output_path, page, &sidebar, &config);
+            println!("[Done] {}", page.title);
         }
     }
 
-    /// Move css / js into the output directory
-    fn move_assets(dist: &str, assets_dir: &str) {
+    /// Copy the scripts of the special languages used by at least one page,
+    /// returns the languages whose script is missing from the vendor directory
+    fn move_vendor_scripts(config: &Config, languages: &BTreeSet<&'static str>) -> BTreeSet<&'static str> {
+        let mut missing = BTreeSet::new();
+        let scripts = languages
+            .iter()
+            .filter_map(|name| SpecialLanguage::find(name))
+            .filter_map(|language| Some((language.name, language.script?)));
+
+        for (name, script) in scripts {
+            let source = Path::new(&config.vendor_dir).join(script);
+            if !source.exists() {
+                println!("[Warning] {} is missing, {} blocks are shown as plain text", source.display(), name);
+                missing.insert(name);
+                continue;
+            }
+            let assets = format!("{}/assets", config.dist_dir);
+            fs::create_dir_all(&assets).unwrap();
+            fs::copy(source, format!("{}/{}", assets, script)).unwrap();
+        }
+        missing
+    }
+
+    /// Move css / js into the output directory, `extra_css` is appended to the styles
+    fn move_assets(dist: &str, assets_dir: &str, extra_css: &str) {
         let ouput_dir = Path::new(dist);
         if !ouput_dir.exists() {
             fs::create_dir_all(dist).unwrap();
@@ -93,28 +149,48 @@ impl Book {
         
         copy_recursively(assets_dir, format!("{dist}/assets")).unwrap();
         
-        let css = include_str!("../assets/style.css");
+        let css = format!("{}\n{}", include_str!("../assets/style.css"), extra_css);
         let path = format!("{}/style.css", dist);
 
         fs::write(path, css).unwrap();
     }
 
-    fn make_sidebar(pages: &Vec<Page>, title: &str) -> String {
+    /// Color and icon of the admonition kinds declared in `book.json`
+    fn admonition_styles(config: &Config) -> String {
+        config
+            .admonitions
+            .iter()
+            .map(|(kind, style)| {
+                let mut rules = vec![];
+                if let Some(color) = &style.color {
+                    rules.push(format!("--admonition-color:{}", color.replace([';', '{', '}'], "")));
+                }
+                if let Some(icon) = &style.icon {
+                    let icon = icon.replace('\\', "\\\\").replace('"', "\\\"");
+                    rules.push(format!("--admonition-icon:\"{}\"", icon));
+                }
+                format!(".admonition-{}{{{}}}", kind.to_lowercase(), rules.join(";"))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn make_sidebar(pages: &[Page], title: &str) -> String {
         let list_item = include_str!("../assets/templates/chapter.html").to_string();
         let mut chapters_list = vec![];
 
-        pages.into_iter().for_each(|page| {
+        pages.iter().for_each(|page| {
             let mut item = list_item.clone();
             let path = md_to_html(&page.path);
-            item = item.replace("$href", &path);
-            item = item.replace("$text", &page.title);
+            item = item.replace("$href", &escape_html(&path));
+            item = item.replace("$text", &escape_html(&page.title));
 
             chapters_list.push(item)
         });
 
         let mut sidebar = include_str!("../assets/templates/sidebar.html").to_string();
         sidebar = sidebar.replace("$chapters", &chapters_list.join(""));
-        sidebar.replace("$title", title)
+        sidebar.replace("$title", &escape_html(title))
     }
 
 
@@ -1,6 +1,5 @@
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fs, path::Path};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -13,6 +12,31 @@ pub struct Config {
     pub root_dir: String,
     #[serde(rename = "distDir")]
     pub dist_dir: String,
+    /// Third-party scripts provided with the book, ex: `mermaid.min.js` for mermaid diagrams
+    #[serde(rename = "vendorDir", default = "default_vendor_dir")]
+    pub vendor_dir: String,
+    /// Let readers tick task list checkboxes, progress is saved in the browser
+    #[serde(rename = "interactiveTasks", default)]
+    pub interactive_tasks: bool,
+    /// HTML written in pages is escaped, passed through or sanitized against `htmlAllowList`
+    #[serde(rename = "rawHtml", default)]
+    pub raw_html: RawHtml,
+    #[serde(rename = "htmlAllowList", default)]
+    pub html_allow_list: HtmlAllowList,
+    #[serde(default)]
+    pub extensions: Extensions,
+    #[serde(default)]
+    pub highlight: Highlight,
+    #[serde(default)]
+    pub toc: Toc,
+    #[serde(default)]
+    pub typography: Typography,
+    /// Reference links available in every page, ex: `[docs][rust]` or `[rust]`
+    #[serde(default)]
+    pub links: BTreeMap<String, Link>,
+    /// Admonition kinds on top of the built-in ones, ex: `:::exercise` or `> [!EXERCISE]`
+    #[serde(default)]
+    pub admonitions: BTreeMap<String, AdmonitionKind>,
     pub pages: Vec<Page>,
 }
 
@@ -41,8 +65,169 @@ impl Config {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn default_vendor_dir() -> String {
+    "./vendor".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Page {
     pub title: String,
     pub path: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub href: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdmonitionKind {
+    /// Title used when the block doesn't set one, defaults to the capitalized kind
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Text or emoji shown before the title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// Any CSS color, used for the border and the title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RawHtml {
+    /// Displayed as text
+    #[default]
+    Escape,
+    /// Written to the page as is
+    Allow,
+    /// Only tags and attributes of the allow list are kept
+    Sanitize,
+}
+
+/// Tags and attributes kept when `rawHtml` is `"sanitize"`, event handlers are always removed
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HtmlAllowList {
+    pub tags: Vec<String>,
+    pub attributes: Vec<String>,
+}
+
+impl Default for HtmlAllowList {
+    fn default() -> Self {
+        let tags = "a abbr audio b blockquote br caption circle code dd details div dl dt em \
+            figcaption figure g hr i iframe img kbd li line mark ol p path picture polygon \
+            polyline pre rect s small source span strong sub summary sup svg table tbody td \
+            text tfoot th thead tr u ul video";
+        let attributes = "align allowfullscreen alt class colspan controls cx cy d fill height \
+            href id loading open points poster r rowspan src stroke stroke-width title type \
+            viewbox width x x1 x2 xmlns y y1 y2";
+
+        HtmlAllowList {
+            tags: tags.split_whitespace().map(String::from).collect(),
+            attributes: attributes.split_whitespace().map(String::from).collect(),
+        }
+    }
+}
+
+/// Syntax on top of CommonMark, every extension can be turned off on its own
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Extensions {
+    /// `~~text~~`
+    pub strikethrough: bool,
+    /// `==text==`
+    pub highlight: bool,
+    /// `^text^`
+    pub superscript: bool,
+    /// `~text~`
+    pub subscript: bool,
+    /// `[[Ctrl+C]]`
+    pub kbd: bool,
+    /// Bare `https://` or `www.` URLs and email addresses
+    pub autolink: bool,
+    /// `$inline$` and `$$display$$` TeX math
+    pub math: bool,
+    /// `Term` lines followed by `: definition` lines
+    #[serde(rename = "definitionLists")]
+    pub definition_lists: bool,
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Extensions {
+            strikethrough: true,
+            highlight: true,
+            superscript: true,
+            subscript: true,
+            kbd: true,
+            autolink: true,
+            math: true,
+            definition_lists: true,
+        }
+    }
+}
+
+/// Build time syntax highlighting of code blocks
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Highlight {
+    /// Bundled theme name, ex: `InspiredGitHub`, or the path of a `.tmTheme` file
+    pub theme: String,
+    /// Extra `.sublime-syntax` files for languages that aren't bundled
+    pub grammars: Vec<String>,
+}
+
+impl Default for Highlight {
+    fn default() -> Self {
+        Highlight {
+            theme: "InspiredGitHub".to_string(),
+            grammars: vec![],
+        }
+    }
+}
+
+/// Text replacements in the prose of the pages, code and URLs are left untouched
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Typography {
+    /// `:tada:` GitHub shortcodes
+    pub emoji: bool,
+    /// Curly quotes, `--` and `---` dashes and `...` ellipses
+    #[serde(rename = "smartPunctuation")]
+    pub smart_punctuation: bool,
+    /// Language of the quotation marks, ex: `en`, `fr` or `de`
+    pub locale: String,
+}
+
+impl Default for Typography {
+    fn default() -> Self {
+        Typography {
+            emoji: false,
+            smart_punctuation: false,
+            locale: "en".to_string(),
+        }
+    }
+}
+
+/// "On this page" outline, only headings between the two depths are listed
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Toc {
+    #[serde(rename = "minDepth")]
+    pub min_depth: u8,
+    #[serde(rename = "maxDepth")]
+    pub max_depth: u8,
+}
+
+impl Default for Toc {
+    fn default() -> Self {
+        Toc {
+            min_depth: 2,
+            max_depth: 3,
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    config::{Config, Link},
+    highlight::Highlighter,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap, HashSet},
+};
+
+/// A problem found while parsing a page, reported without failing the build
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// 1-based line in the page source
+    pub line: usize,
+    pub message: String,
+}
+
+/// Settings and link definitions shared by every token of the page being parsed
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub config: &'a Config,
+    /// Code blocks are left plain without it
+    pub highlighter: Option<&'a Highlighter>,
+    /// Full text of the page
+    source: &'a str,
+    /// Line of the page where the lines being parsed start, nested blocks parse lines of their own
+    first_line: Cell<usize>,
+    /// Reference links defined in the page itself
+    links: HashMap<String, Link>,
+    /// Reference links shared in `book.json`
+    book_links: HashMap<String, Link>,
+    /// Footnote definitions of the page with the line they start at, ex: `[^1]: text`
+    footnotes: HashMap<String, (usize, Vec<&'a str>)>,
+    /// Labels of the referenced footnotes in order of first reference, with their reference count
+    footnote_refs: RefCell<Vec<(String, usize)>>,
+    /// Element ids already used in the page
+    ids: RefCell<HashSet<String>>,
+    /// Special code block languages of the page, ex: `mermaid`
+    languages: RefCell<BTreeSet<&'static str>>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(config: &'a Config, source: &'a str) -> Self {
+        let book_links = config
+            .links
+            .iter()
+            .map(|(label, link)| (Context::normalize_label(label), link.clone()))
+            .collect();
+
+        Context {
+            config,
+            highlighter: None,
+            source,
+            first_line: Cell::new(1),
+            links: HashMap::new(),
+            book_links,
+            footnotes: HashMap::new(),
+            footnote_refs: RefCell::new(vec![]),
+            ids: RefCell::new(HashSet::new()),
+            languages: RefCell::new(BTreeSet::new()),
+            diagnostics: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn with_highlighter(mut self, highlighter: &'a Highlighter) -> Self {
+        self.highlighter = Some(highlighter);
+        self
+    }
+
+    /// Add a page link definition, only the first definition of a label is kept
+    pub fn add_link(&mut self, label: &str, link: Link) {
+        self.links
+            .entry(Context::normalize_label(label))
+            .or_insert(link);
+    }
+
+    /// Find a link definition, page definitions win over the ones from `book.json`
+    pub fn link(&self, label: &str) -> Option<&Link> {
+        let label = Context::normalize_label(label);
+        self.links
+            .get(&label)
+            .or_else(|| self.book_links.get(&label))
+    }
+
+    /// Add a footnote definition, only the first definition of a label is kept
+    pub fn add_footnote(&mut self, label: &str, line: usize, lines: Vec<&'a str>) {
+        self.footnotes
+            .entry(Context::normalize_label(label))
+            .or_insert((line, lines));
+    }
+
+    /// Record a reference to a footnote, returns its number and which reference this is
+    pub fn footnote_ref(&self, label: &str) -> Option<(usize, usize)> {
+        let label = Context::normalize_label(label);
+        if !self.footnotes.contains_key(&label) {
+            return None;
+        }
+
+        let mut refs = self.footnote_refs.borrow_mut();
+        let idx = match refs.iter().position(|(l, _)| *l == label) {
+            Some(idx) => idx,
+            None => {
+                refs.push((label, 0));
+                refs.len() - 1
+            }
+        };
+        refs[idx].1 += 1;
+
+        Some((idx + 1, refs[idx].1))
+    }
+
+    /// Line and content of a referenced footnote by number
+    pub fn footnote(&self, number: usize) -> Option<(usize, Vec<&'a str>)> {
+        let refs = self.footnote_refs.borrow();
+        let (label, _) = refs.get(number.checked_sub(1)?)?;
+        Some(self.footnotes[label].clone())
+    }
+
+    pub fn footnote_ref_count(&self, number: usize) -> usize {
+        let refs = self.footnote_refs.borrow();
+        refs.get(number - 1).map_or(0, |(_, count)| *count)
+    }
+
+    /// Keep `fn-1`, `fnref-1` and `fnref-1-2` away from headings, footnotes are only numbered
+    /// while parsing so every id a definition of the page could get is reserved
+    pub fn reserve_footnote_ids(&self) {
+        let source = self.source.to_lowercase();
+        let max_refs = self
+            .footnotes
+            .keys()
+            .map(|label| source.matches(&format!("[^{}]", label)).count())
+            .max()
+            .unwrap_or(0);
+
+        let mut ids = self.ids.borrow_mut();
+        for number in 1..=self.footnotes.len() {
+            ids.insert(format!("fn-{}", number));
+            ids.insert(format!("fnref-{}", number));
+            for occurrence in 2..=max_refs {
+                ids.insert(format!("fnref-{}-{}", number, occurrence));
+            }
+        }
+    }
+
+    /// Mark an id as used, returns false if it already was
+    pub fn claim_id(&self, id: &str) -> bool {
+        self.ids.borrow_mut().insert(id.to_string())
+    }
+
+    /// First free id among `slug`, `slug-1`, `slug-2`...
+    pub fn unique_id(&self, slug: &str) -> String {
+        let mut id = slug.to_string();
+        let mut suffix = 0;
+        while !self.claim_id(&id) {
+            suffix += 1;
+            id = format!("{}-{}", slug, suffix);
+        }
+        id
+    }
+
+    pub fn use_language(&self, name: &'static str) {
+        self.languages.borrow_mut().insert(name);
+    }
+
+    pub fn languages(&self) -> Vec<&'static str> {
+        self.languages.borrow().iter().copied().collect()
+    }
+
+    /// Start the line numbers of the lines being parsed at `line`, returns the previous start
+    pub fn set_first_line(&self, line: usize) -> usize {
+        self.first_line.replace(line)
+    }
+
+    /// Line of the page of `lines[idx]`, `lines` being the ones given to `Document::parse_nodes`
+    pub fn line_number(&self, idx: usize) -> usize {
+        self.first_line.get() + idx
+    }
+
+    /// Report a problem about a line of the page
+    pub fn warn(&self, line: usize, message: &str) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            line,
+            message: message.to_string(),
+        });
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics.into_inner()
+    }
+
+    /// Labels are case insensitive and any whitespace counts as a single space
+    fn normalize_label(label: &str) -> String {
+        label
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase()
+    }
+}
@@ -0,0 +1,93 @@
+use crate::utils::escape_html;
+
+/// Code block language rendered as something other than highlighted code
+#[derive(Debug)]
+pub struct SpecialLanguage {
+    pub name: &'static str,
+    /// Turns the content of the code block into HTML
+    pub render: fn(&str) -> String,
+    /// File of the book's `vendorDir` needed to display the result, copied to the dist assets when used
+    pub script: Option<&'static str>,
+    /// Run once the script is loaded
+    pub init: &'static str,
+}
+
+static SPECIAL_LANGUAGES: [SpecialLanguage; 2] = [
+    SpecialLanguage {
+        name: "mermaid",
+        render: mermaid,
+        script: Some("mermaid.min.js"),
+        init: "mermaid.initialize({ startOnLoad: true });",
+    },
+    SpecialLanguage {
+        name: "svgbob",
+        render: svgbob,
+        script: None,
+        init: "",
+    },
+];
+
+impl SpecialLanguage {
+    pub fn find(lang: &str) -> Option<&'static SpecialLanguage> {
+        SPECIAL_LANGUAGES.iter().find(|l| l.name == lang)
+    }
+
+    /// Tags loading the script of the language in a page, if it needs one
+    pub fn script_html(&self) -> Option<String> {
+        self.script.map(|script| {
+            format!(
+                "<script src='./assets/{}'></script>\n<script>{}</script>",
+                script, self.init
+            )
+        })
+    }
+}
+
+/// Drawn in the browser by the mermaid script, it reads the text content of the block
+fn mermaid(code: &str) -> String {
+    format!("<pre class='mermaid'>{}</pre>", escape_html(code))
+}
+
+/// ASCII art drawn as an SVG at build time, in the page's text color
+fn svgbob(code: &str) -> String {
+    let settings = svgbob::Settings {
+        stroke_color: "currentColor".into(),
+        fill_color: "currentColor".into(),
+        include_backdrop: false,
+        ..Default::default()
+    };
+    svgbob::to_svg_with_settings(code, &settings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpecialLanguage;
+
+    #[test]
+    fn it_render_special_languages() {
+        let mermaid = SpecialLanguage::find("mermaid").unwrap();
+        assert_eq!(
+            (mermaid.render)("a --> b"),
+            "<pre class='mermaid'>a --&gt; b</pre>"
+        );
+        assert!(mermaid
+            .script_html()
+            .unwrap()
+            .starts_with("<script src='./assets/mermaid.min.js'>"));
+        assert!(SpecialLanguage::find("rust").is_none());
+    }
+
+    #[test]
+    fn it_draw_ascii_art() {
+        let svgbob = SpecialLanguage::find("svgbob").unwrap();
+        let svg = (svgbob.render)("+-----------+\n| parser v2 |--> go\n'-----------'");
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"160\" height=\"64\" class=\"svgbob\">"));
+        // Labels inside and outside the box stay text
+        assert!(svg.contains("<text x=\"18\" y=\"28\" >parser</text>"));
+        assert!(svg.contains("<text x=\"74\" y=\"28\" >v2</text>"));
+        assert!(svg.contains("<text x=\"138\" y=\"28\" >go</text>"));
+        assert!(svg.contains("<line x1=\"4\" y1=\"8\" x2=\"100\" y2=\"8\" class=\"solid\"></line>"));
+        assert!(!svg.contains("<rect"));
+    }
+}
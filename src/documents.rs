@@ -1,65 +1,446 @@
 use crate::{
+    config::{Config, Page},
+    context::{Context, Diagnostic},
+    diagrams::SpecialLanguage,
+    front_matter::FrontMatter,
+    highlight::Highlighter,
     node::Node,
-    tokens::{CodeBlock, Heading, LineBreak, List, Paragraph, Quote},
-    utils::replace_html,
+    tokens::{
+        Admonition, CodeBlock, CodeFence, DefinitionList, Footnote, FootnoteDefinition, Heading,
+        HtmlBlock, LineBreak, LinkDefinition, List, MathBlock, Paragraph, Quote, Table,
+        TableOfContents, ThematicBreak,
+    },
+    utils::escape_html,
 };
-use std::{fs, path::Path};
+use regex::{Captures, Regex};
+use std::{fs, path::Path, sync::LazyLock};
 
 #[derive(Debug)]
 pub struct Document {
     nodes: Vec<Node>,
+    /// Referenced footnotes in order of first reference
+    footnotes: Vec<Footnote>,
+    toc: TableOfContents,
+    pub front_matter: FrontMatter,
+    /// Special code block languages used in the page, their scripts are added to it
+    pub languages: Vec<&'static str>,
+    /// Problems found while parsing, the page is still rendered
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl Document {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Document {
+    pub fn from_file<P: AsRef<Path>>(path: P, config: &Config, highlighter: &Highlighter) -> Document {
         let file = fs::read_to_string(path).unwrap();
-        let lines = file.split("\n").collect::<Vec<&str>>();
+        Document::parse(&file, config, Some(highlighter))
+    }
+
+    pub fn parse(text: &str, config: &Config, highlighter: Option<&Highlighter>) -> Document {
+        let mut ctx = Context::new(config, text);
+        if let Some(highlighter) = highlighter {
+            ctx = ctx.with_highlighter(highlighter);
+        }
+
+        let (front_matter, body) = match FrontMatter::split(text) {
+            (Some(Ok(front_matter)), body) => (front_matter, body),
+            (Some(Err(error)), _) => {
+                let message = format!("invalid front matter, rendered as markdown: {}", error);
+                ctx.warn(1, &message);
+                (FrontMatter::default(), text)
+            }
+            (None, _) => (FrontMatter::default(), text),
+        };
+        // Diagnostics keep the line numbers of the file
+        let first_line = text[..text.len() - body.len()].matches('\n').count() + 1;
+        let mut lines = body.split('\n').collect::<Vec<&str>>();
+        Document::collect_definitions(&mut lines, first_line, &mut ctx);
+        ctx.reserve_footnote_ids();
+        let mut nodes = Document::parse_nodes(&lines, first_line, &ctx);
+
+        let toc = TableOfContents::new(&nodes, config.toc.min_depth, config.toc.max_depth);
+        for node in nodes.iter_mut() {
+            if let Node::TableOfContents(marker) = node {
+                *marker = toc.clone();
+            }
+        }
+
+        // Footnotes can reference other footnotes, which get the next numbers
+        let mut footnote_nodes = vec![];
+        while let Some((line, lines)) = ctx.footnote(footnote_nodes.len() + 1) {
+            footnote_nodes.push(Document::parse_nodes(&lines, line, &ctx));
+        }
+        let footnotes = footnote_nodes
+            .into_iter()
+            .enumerate()
+            .map(|(idx, children)| Footnote::new(idx + 1, ctx.footnote_ref_count(idx + 1), children))
+            .collect();
+
+        Document {
+            nodes,
+            footnotes,
+            toc,
+            front_matter,
+            languages: ctx.languages(),
+            diagnostics: ctx.into_diagnostics(),
+        }
+    }
+
+    /// Move link and footnote definitions into the context, their lines are blanked so they aren't rendered
+    fn collect_definitions<'a>(lines: &mut [&'a str], first_line: usize, ctx: &mut Context<'a>) {
+        let mut fence: Option<CodeFence> = None;
+        let mut idx = 0;
+        while idx < lines.len() {
+            // Definitions inside fenced code are just code
+            if let Some(open) = &fence {
+                if open.is_closed_by(lines[idx]) {
+                    fence = None;
+                }
+                idx += 1;
+                continue;
+            }
 
+            if let Some(open) = CodeFence::new(lines[idx]) {
+                fence = Some(open);
+                idx += 1;
+                continue;
+            }
+
+            // A definition can't interrupt a paragraph
+            let is_block_start = idx == 0 || lines[idx - 1].trim().is_empty();
+            if let (Some(definition), end_idx) = LinkDefinition::new(lines, idx) {
+                if is_block_start {
+                    ctx.add_link(&definition.label, definition.link);
+                    lines[idx..end_idx].fill("");
+                    idx = end_idx;
+                    continue;
+                }
+            }
+
+            if let (Some(definition), end_idx) = FootnoteDefinition::new(lines, idx, ctx) {
+                if is_block_start {
+                    ctx.add_footnote(&definition.label, first_line + idx, definition.lines);
+                    lines[idx..end_idx].fill("");
+                    idx = end_idx;
+                    continue;
+                }
+            }
+
+            idx += 1;
+        }
+    }
+
+    /// Parse a sequence of lines into blocks, also used for content nested in other blocks.
+    /// `first_line` is the line of the page where `lines` start.
+    pub fn parse_nodes(lines: &[&str], first_line: usize, ctx: &Context) -> Vec<Node> {
+        let parent_first_line = ctx.set_first_line(first_line);
         let mut nodes = vec![];
         let mut idx = 0;
         while idx < lines.len() {
             let line = lines[idx];
 
-            if let Some(_) = LineBreak::new(line) {
+            if LineBreak::new(line).is_some() {
                 // Should be ingored
-            } else if let Some(heading) = Heading::new(line) {
+            } else if line.trim() == "[TOC]" {
+                nodes.push(Node::TableOfContents(TableOfContents::default()));
+            } else if let (Some(code_block), curr_idx) = CodeBlock::new(lines, idx, ctx) {
+                // Checked first as indented code can look like any other block
+                nodes.push(Node::CodeBlock(code_block));
+                idx = curr_idx - 1;
+            } else if let (Some(html), curr_idx) = HtmlBlock::new(lines, idx, ctx) {
+                nodes.push(Node::Html(html));
+                idx = curr_idx - 1;
+            } else if let (Some(math), curr_idx) = MathBlock::new(lines, idx, ctx) {
+                nodes.push(Node::Math(math));
+                idx = curr_idx - 1;
+            } else if let Some(thematic_break) = ThematicBreak::new(line) {
+                nodes.push(Node::ThematicBreak(thematic_break));
+            } else if let Some(heading) = Heading::new(lines, idx, ctx) {
                 nodes.push(Node::Heading(heading));
-            } else if let (Some(list), curr_idx) = List::new(&lines, idx) {
+            } else if let (Some(table), curr_idx) = Table::new(lines, idx, ctx) {
+                nodes.push(Node::Table(table));
+                idx = curr_idx - 1;
+            } else if let (Some(list), curr_idx) = List::new(lines, idx, ctx) {
                 nodes.push(Node::List(list));
                 idx = curr_idx - 1;
-            } else if let (Some(code_block), curr_idx) = CodeBlock::new(&lines, idx) {
-                nodes.push(Node::CodeBlock(code_block));
-                idx = curr_idx;
-            } else if let (Some(quote), curr_idx) = Quote::new(&lines, idx) {
+            } else if let (Some(admonition), curr_idx) = Admonition::new(lines, idx, ctx) {
+                nodes.push(Node::Admonition(admonition));
+                idx = curr_idx - 1;
+            } else if let (Some(quote), curr_idx) = Quote::new(lines, idx, ctx) {
                 nodes.push(Node::Quote(quote));
-                idx = curr_idx;
-            } else if let Some(paragraph) = Paragraph::new(line) {
-                nodes.push(Node::Paragraph(paragraph))
+                idx = curr_idx - 1;
+            } else if let (Some(list), curr_idx) = DefinitionList::new(lines, idx, ctx) {
+                nodes.push(Node::DefinitionList(list));
+                idx = curr_idx - 1;
+            } else if let (Some(heading), curr_idx) = Heading::setext(lines, idx, ctx) {
+                nodes.push(Node::Heading(heading));
+                idx = curr_idx - 1;
+            } else if let (Some(paragraph), curr_idx) = Paragraph::new(lines, idx, ctx) {
+                nodes.push(Node::Paragraph(paragraph));
+                idx = curr_idx - 1;
             }
 
             idx += 1;
         }
 
-        Document { nodes }
+        ctx.set_first_line(parent_first_line);
+        nodes
+    }
+
+    /// Title of the page, the front matter one wins over `book.json`
+    pub fn title<'a>(&'a self, page: &'a Page) -> &'a str {
+        self.front_matter.title.as_deref().unwrap_or(&page.title)
+    }
+
+    /// `<meta>` tags of the page, falling back to the book description and author
+    fn meta_html(&self, config: &Config) -> String {
+        let description = self.front_matter.description.as_deref().unwrap_or(&config.description);
+        let authors = if self.front_matter.authors.is_empty() {
+            config.author.clone()
+        } else {
+            self.front_matter.authors.join(", ")
+        };
+        [
+            ("description", description.to_string()),
+            ("keywords", self.front_matter.keywords.join(", ")),
+            ("author", authors),
+        ]
+        .iter()
+        .filter(|(_, content)| !content.is_empty())
+        .map(|(name, content)| format!("<meta name='{}' content='{}' />", name, escape_html(content)))
+        .collect::<Vec<String>>()
+        .join("\n    ")
     }
 
-    pub fn into_html(&self, sidebar: &str) -> String {
-        let html_body = self
+    pub fn to_html(&self, page: &Page, sidebar: &str, config: &Config) -> String {
+        let mut html_body = self
             .nodes
             .iter()
-            .map(|n| n.into_html())
+            .map(|n| n.to_html())
             .collect::<Vec<String>>()
             .join("\n");
 
+        if !self.footnotes.is_empty() {
+            let footnotes = self
+                .footnotes
+                .iter()
+                .map(|f| f.to_html())
+                .collect::<Vec<String>>()
+                .join("\n");
+            html_body.push_str(&format!("\n<section class='footnotes'><ol>{}</ol></section>", footnotes));
+        }
+
+        let mut scripts = vec![];
+        if config.interactive_tasks {
+            let tasks = include_str!("../assets/tasks.js");
+            scripts.push(format!("<script>{}</script>", tasks));
+        }
+        scripts.extend(
+            self.languages
+                .iter()
+                .filter_map(|name| SpecialLanguage::find(name)?.script_html()),
+        );
+
         let html_doc = include_str!("../assets/templates/base.html");
+        let title = if config.bookname.is_empty() {
+            self.title(page).to_string()
+        } else {
+            format!("{} - {}", self.title(page), config.bookname)
+        };
 
-        replace_html(html_doc, &html_body, sidebar)
+        // Single pass, so placeholders written in the inserted values are kept as is
+        static RE_PLACEHOLDER: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"\$(title|meta|sidebar|content|toc|scripts)\b").unwrap());
+        let html_doc = RE_PLACEHOLDER.replace_all(html_doc, |caps: &Captures| match &caps[1] {
+            "title" => escape_html(&title),
+            "meta" => self.meta_html(config),
+            "sidebar" => sidebar.to_string(),
+            "content" => html_body.clone(),
+            "toc" => self.toc.sidebar_html(),
+            _ => scripts.join("\n"),
+        });
+        html_doc.into_owned()
     }
 
-    pub fn save<P: AsRef<Path>>(&self, path: P, sidebar: &str) {
-        let html = self.into_html(sidebar);
+    pub fn save<P: AsRef<Path>>(&self, path: P, page: &Page, sidebar: &str, config: &Config) {
+        let html = self.to_html(page, sidebar, config);
         // Move image if any
         fs::write(path, html).unwrap();
     }
 
 }
+
+#[cfg(test)]
+mod test {
+    use super::Document;
+    use crate::config::{Config, Page};
+
+    #[test]
+    fn it_number_footnotes_by_first_reference() {
+        let config = Config::new();
+        let text = "Second[^b], first[^a] and again[^b]. Missing[^c].\n\n[^a]: Note A[^z]\n[^b]: Note B\n\n    ```\n    code\n    ```\n[^z]: Nested\n";
+        let doc = Document::parse(text, &config, None);
+        let html = doc
+            .nodes
+            .iter()
+            .map(|n| n.to_html())
+            .collect::<String>();
+
+        assert_eq!(
+            html,
+            "<p>Second<sup class='footnote-ref' id='fnref-1'><a href='#fn-1'>1</a></sup>, first<sup class='footnote-ref' id='fnref-2'><a href='#fn-2'>2</a></sup> and again<sup class='footnote-ref' id='fnref-1-2'><a href='#fn-1'>1</a></sup>. Missing[^c].</p>"
+        );
+
+        let footnotes = doc
+            .footnotes
+            .iter()
+            .map(|f| f.to_html())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            footnotes,
+            [
+                "<li id='fn-1'><p>Note B</p>\n<pre class='hl-code'><code><span class='code-line'>code\n</span></code></pre><p><a href='#fnref-1' class='footnote-backref'>&#8617;&#xFE0E;</a> <a href='#fnref-1-2' class='footnote-backref'>&#8617;&#xFE0E;<sup>2</sup></a></p></li>",
+                "<li id='fn-2'><p>Note A<sup class='footnote-ref' id='fnref-3'><a href='#fn-3'>3</a></sup> <a href='#fnref-2' class='footnote-backref'>&#8617;&#xFE0E;</a></p></li>",
+                "<li id='fn-3'><p>Nested <a href='#fnref-3' class='footnote-backref'>&#8617;&#xFE0E;</a></p></li>",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keep_footnote_ids_away_from_headings() {
+        let config = Config::new();
+        let text = "## fn 1\n\n## Fnref 1 2\n\nText[^a] and[^a]\n\n[^a]: Note";
+        let doc = Document::parse(text, &config, None);
+        let html = doc
+            .nodes
+            .iter()
+            .map(|n| n.to_html())
+            .collect::<Vec<String>>();
+
+        assert!(html[0].starts_with("<h2 id='fn-1-1'>"));
+        assert!(html[1].starts_with("<h2 id='fnref-1-2-1'>"));
+        assert!(doc.footnotes[0].to_html().starts_with("<li id='fn-1'>"));
+    }
+
+    #[test]
+    fn it_build_toc_from_headings() {
+        let config = Config::new();
+        let text = "# Book\n\n[TOC]\n\n## Intro\n#### Skipped level\n### Details\n## Next *one*\n> ## Quoted";
+        let doc = Document::parse(text, &config, None);
+
+        let list = "<ul class='toc-list'><li><a href='#intro'>Intro</a><ul class='toc-list'><li><a href='#details'>Details</a></li></ul></li><li><a href='#next-one'>Next one</a></li></ul>";
+        assert_eq!(doc.nodes[1].to_html(), format!("<nav class='toc-inline'>{}</nav>", list));
+        assert_eq!(
+            doc.toc.sidebar_html(),
+            format!("<nav class='toc'><p class='toc-title'>On this page</p>{}</nav>", list)
+        );
+    }
+
+    #[test]
+    fn it_follow_commonmark_heading_rules() {
+        let config = Config::new();
+        let text = "####### seven\n#hashtag\n\n## Closed ##\n# Kept# \\#\n### ###\n\nMulti\nline\n===\n\nSub\n---\n\n* * *\n- item\n___\n\ntext\n***";
+        let html = Document::parse(text, &config, None)
+            .nodes
+            .iter()
+            .map(|n| n.to_html())
+            .collect::<Vec<String>>();
+
+        let anchor = |id: &str| format!("<a class='heading-anchor' href='#{}' aria-label='Permalink'>#</a>", id);
+        assert_eq!(
+            html,
+            [
+                "<p>####### seven\n#hashtag</p>".to_string(),
+                format!("<h2 id='closed'>Closed{}</h2>", anchor("closed")),
+                format!("<h1 id='kept'>Kept# #{}</h1>", anchor("kept")),
+                format!("<h3 id='section'>{}</h3>", anchor("section")),
+                format!("<h1 id='multi-line'>Multi\nline{}</h1>", anchor("multi-line")),
+                format!("<h2 id='sub'>Sub{}</h2>", anchor("sub")),
+                "<hr/>".to_string(),
+                "<ul><li>item</li></ul>".to_string(),
+                "<hr/>".to_string(),
+                "<p>text</p>".to_string(),
+                "<hr/>".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_convert_math_and_report_unsupported_commands() {
+        let config = Config::new();
+        let text = "Costs $5 and $10, area $\\pi r^2$.\n\n$$\na = b\n\\\\ \\weird{x}\n$$\nSome $\\oops$ here";
+        let doc = Document::parse(text, &config, None);
+        let html = doc
+            .nodes
+            .iter()
+            .map(|n| n.to_html())
+            .collect::<Vec<String>>();
+
+        assert_eq!(html.len(), 3);
+        assert!(html[0].starts_with("<p>Costs $5 and $10, area <math><semantics><mrow><mi>π</mi>"));
+        assert!(html[1].starts_with("<math display='block'><semantics><mtable"));
+        assert!(html[2].contains("<merror><mtext>\\oops</mtext></merror>"));
+
+        let diagnostics = doc
+            .diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            [
+                (5, "unsupported command `\\weird`"),
+                (7, "unsupported command `\\oops`")
+            ]
+        );
+    }
+
+    #[test]
+    fn it_report_math_problems_at_their_line() {
+        let config = Config::new();
+        let text = "---\ntitle: Math\n---\n$\\oops$ and $\\frac{a}{b$ text\n\n> quote\n> with $\\oops$\n\n- item\n  lazy $\\frac$[^1]\n\n[^1]: Note\n    with $\\oops$";
+        let doc = Document::parse(text, &config, None);
+        let lines = doc.diagnostics.iter().map(|d| d.line).collect::<Vec<_>>();
+
+        // The same text in several places, or after a longer version of it, keeps its own line
+        assert_eq!(lines, [4, 4, 7, 10, 13]);
+    }
+
+    #[test]
+    fn it_read_front_matter() {
+        let mut config = Config::new();
+        config.bookname = "Guide".to_string();
+        config.author = "Team".to_string();
+        let page = Page {
+            title: "Intro".to_string(),
+            path: "intro.md".to_string(),
+        };
+
+        let text = "---\ntitle: Getting 'started'\ndescription: First $toc steps\nkeywords: [a, b]\n---\nText\n\n---\n";
+        let doc = Document::parse(text, &config, None);
+        assert_eq!(doc.title(&page), "Getting 'started'");
+        assert_eq!(doc.nodes.len(), 2);
+        let html = doc.to_html(&page, "", &config);
+        assert!(html.contains("<title>Getting &#39;started&#39; - Guide</title>"));
+        assert!(html.contains("<meta name='description' content='First $toc steps' />\n    <meta name='keywords' content='a, b' />\n    <meta name='author' content='Team' />"));
+
+        let doc = Document::parse("---\nNot: [front\n---\n\n# Title", &config, None);
+        assert_eq!(doc.title(&page), "Intro");
+        assert_eq!(doc.nodes.len(), 3);
+        assert_eq!(doc.diagnostics[0].line, 1);
+        assert!(doc.diagnostics[0].message.starts_with("invalid front matter"));
+    }
+
+    #[test]
+    fn it_keep_template_placeholders_in_page_text() {
+        let config = Config::new();
+        let page = Page {
+            title: "Intro".to_string(),
+            path: "intro.md".to_string(),
+        };
+        let doc = Document::parse("## Chapters list\n\nMain page content and $sidebar", &config, None);
+        let html = doc.to_html(&page, "<ul class='chapters'></ul>", &config);
+
+        assert_eq!(html.matches("<ul class='chapters'></ul>").count(), 1);
+        assert!(html.contains("<p>Main page content and $sidebar</p>"));
+        assert!(html.contains("<a href='#chapters-list'>Chapters list</a>"));
+    }
+}
@@ -0,0 +1,87 @@
+use serde::Deserialize;
+
+/// Page metadata, YAML between `---` lines or TOML between `+++` lines at the top of the file
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    /// Replaces the title of the page from `book.json`
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+    pub authors: Vec<String>,
+    /// Drafts are left out of the build and the sidebar
+    pub draft: bool,
+}
+
+impl FrontMatter {
+    /// Front matter at the start of the text and the markdown after it,
+    /// the text is returned whole when it doesn't start with a closed front matter block
+    pub fn split(text: &str) -> (Option<Result<FrontMatter, String>>, &str) {
+        let mut lines = text.split_inclusive('\n');
+        let first = lines.next().unwrap_or_default();
+        let fence = first.trim_end();
+        if fence != "---" && fence != "+++" {
+            return (None, text);
+        }
+
+        let mut offset = first.len();
+        for line in lines {
+            let closing = line.trim_end();
+            if closing == fence || (fence == "---" && closing == "...") {
+                let raw = &text[first.len()..offset];
+                let front_matter = if raw.trim().is_empty() {
+                    Ok(FrontMatter::default())
+                } else if fence == "---" {
+                    serde_yaml::from_str(raw).map_err(|e| e.to_string())
+                } else {
+                    toml::from_str(raw).map_err(|e| e.to_string())
+                };
+                return (Some(front_matter), &text[offset + line.len()..]);
+            }
+            offset += line.len();
+        }
+        (None, text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FrontMatter;
+
+    #[test]
+    fn it_split_yaml_and_toml_front_matter() {
+        let text = "---\ntitle: Setup\nkeywords: [install, cli]\ndraft: true\n---\n# Setup\n";
+        let (front_matter, body) = FrontMatter::split(text);
+        assert_eq!(
+            front_matter,
+            Some(Ok(FrontMatter {
+                title: Some("Setup".to_string()),
+                keywords: vec!["install".to_string(), "cli".to_string()],
+                draft: true,
+                ..FrontMatter::default()
+            }))
+        );
+        assert_eq!(body, "# Setup\n");
+
+        let text = "+++\ndescription = \"How to\"\nauthors = [\"Ada\"]\n+++\n\nText";
+        let (front_matter, body) = FrontMatter::split(text);
+        assert_eq!(
+            front_matter,
+            Some(Ok(FrontMatter {
+                description: Some("How to".to_string()),
+                authors: vec!["Ada".to_string()],
+                ..FrontMatter::default()
+            }))
+        );
+        assert_eq!(body, "\nText");
+    }
+
+    #[test]
+    fn it_keep_text_without_front_matter() {
+        assert_eq!(FrontMatter::split("---\nText"), (None, "---\nText"));
+        assert_eq!(FrontMatter::split("Title\n---\n"), (None, "Title\n---\n"));
+
+        let (front_matter, _) = FrontMatter::split("---\nJust a paragraph\n---\n");
+        assert!(front_matter.unwrap().is_err());
+    }
+}
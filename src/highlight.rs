@@ -0,0 +1,109 @@
+use crate::config::Config;
+use std::fs;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{css_for_theme_with_class_style, line_tokens_to_classed_spans, ClassStyle},
+    parsing::{ParseState, ScopeStack, SyntaxDefinition, SyntaxSet},
+};
+
+/// Classes are prefixed so they can't clash with the book styles
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Bundled grammars plus the ones registered in `book.json`, built once per book
+#[derive(Debug)]
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl Highlighter {
+    pub fn new(config: &Config) -> Self {
+        let mut syntaxes = SyntaxSet::load_defaults_nonewlines();
+        if !config.highlight.grammars.is_empty() {
+            let mut builder = syntaxes.into_builder();
+            for path in &config.highlight.grammars {
+                let grammar = fs::read_to_string(path)
+                    .unwrap_or_else(|e| panic!("Failed to read grammar `{}`: {}", path, e));
+                let syntax = SyntaxDefinition::load_from_str(&grammar, false, None)
+                    .unwrap_or_else(|e| panic!("Invalid grammar `{}`: {}", path, e));
+                builder.add(syntax);
+            }
+            syntaxes = builder.build();
+        }
+
+        let theme_name = &config.highlight.theme;
+        let theme = if theme_name.ends_with(".tmTheme") {
+            ThemeSet::get_theme(theme_name)
+                .unwrap_or_else(|e| panic!("Invalid theme `{}`: {}", theme_name, e))
+        } else {
+            ThemeSet::load_defaults()
+                .themes
+                .remove(theme_name)
+                .unwrap_or_else(|| panic!("Unknown highlight theme `{}`", theme_name))
+        };
+
+        Highlighter { syntaxes, theme }
+    }
+
+    /// Highlight each line on its own, spans still open at the end of a line are reopened on the next one
+    pub fn highlight(&self, lang: &str, lines: &[String]) -> Option<Vec<String>> {
+        let syntax = self.syntaxes.find_syntax_by_token(lang)?;
+        let mut state = ParseState::new(syntax);
+        let mut stack = ScopeStack::new();
+
+        let mut html_lines = vec![];
+        for line in lines {
+            let ops = state.parse_line(line, &self.syntaxes).ok()?;
+
+            let mut html = String::new();
+            for scope in stack.as_slice() {
+                let classes = scope
+                    .build_string()
+                    .split('.')
+                    .map(|atom| format!("hl-{}", atom))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                html.push_str(&format!("<span class=\"{}\">", classes));
+            }
+
+            let open = stack.len() as isize;
+            let (spans, delta) =
+                line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, &mut stack).ok()?;
+            html.push_str(&spans);
+            html.push_str(&"</span>".repeat((open + delta).max(0) as usize));
+            html_lines.push(html);
+        }
+
+        Some(html_lines)
+    }
+
+    /// Theme colors for the `hl-` classes, `hl-code` is the code block itself
+    pub fn css(&self) -> String {
+        css_for_theme_with_class_style(&self.theme, CLASS_STYLE).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Highlighter;
+    use crate::config::Config;
+
+    #[test]
+    fn it_reopen_spans_on_each_line() {
+        let highlighter = Highlighter::new(&Config::new());
+        let lines = ["/* a", "b */ let"].map(String::from);
+        let html = highlighter.highlight("rust", &lines).unwrap();
+
+        for line in &html {
+            assert_eq!(
+                line.matches("<span").count(),
+                line.matches("</span>").count()
+            );
+        }
+        assert!(html[1].starts_with(
+            "<span class=\"hl-source hl-rust\"><span class=\"hl-comment hl-block hl-rust\">b"
+        ));
+        assert!(html[1].contains("<span class=\"hl-storage hl-type hl-rust\">let</span>"));
+        assert!(highlighter.highlight("not-a-language", &lines).is_none());
+    }
+}
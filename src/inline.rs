@@ -0,0 +1,1169 @@
+use crate::{
+    config::RawHtml,
+    context::Context,
+    math::Math,
+    sanitize::{is_safe_url, Sanitizer, HTML_TAG},
+    typography::Typographer,
+    utils::escape_html,
+};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Inline markdown inside a block, nested markup is kept as children
+#[derive(Debug, PartialEq, Eq)]
+pub enum InlineToken {
+    Text(String),
+    Code(String),
+    Emphasis(Vec<InlineToken>),
+    Strong(Vec<InlineToken>),
+    Strikethrough(Vec<InlineToken>),
+    Highlight(Vec<InlineToken>),
+    Superscript(Vec<InlineToken>),
+    Subscript(Vec<InlineToken>),
+    /// Keys of a shortcut like `[[Ctrl+C]]`
+    Kbd(Vec<String>),
+    /// `<https://example.com>` or a bare `https://` / `www.` URL
+    Autolink(String),
+    /// `<me@example.com>` or a bare email address
+    Email(String),
+    Link {
+        href: String,
+        title: Option<String>,
+        children: Vec<InlineToken>,
+    },
+    Image {
+        src: String,
+        alt: String,
+        title: Option<String>,
+        size: ImageSize,
+    },
+    /// `[^label]` reference to a footnote defined in the page
+    FootnoteRef {
+        number: usize,
+        /// 1 for the first reference to the footnote, 2 for the second...
+        occurrence: usize,
+    },
+    /// Inline HTML tag passed through as is, only when raw HTML is allowed
+    Html(String),
+    /// `$x^2$` converted to MathML
+    Math {
+        tex: String,
+        mathml: String,
+    },
+    SoftBreak,
+    HardBreak,
+}
+
+impl InlineToken {
+    /// Tokenize the text of a block (paragraph, heading, table cell...), starting at `line` of the page
+    pub fn parse(text: &str, line: usize, ctx: &Context) -> Vec<InlineToken> {
+        Tokenizer::new(text, line, ctx).tokenize()
+    }
+
+    /// Render a list of sibling tokens
+    pub fn render(tokens: &[InlineToken]) -> String {
+        tokens.iter().map(|t| t.to_html()).collect()
+    }
+
+    /// Text content without any markup, used where HTML isn't allowed (ex: image alt)
+    pub fn plain_text(tokens: &[InlineToken]) -> String {
+        tokens
+            .iter()
+            .map(|token| match token {
+                InlineToken::Text(text) | InlineToken::Code(text) => text.clone(),
+                InlineToken::Emphasis(children)
+                | InlineToken::Strong(children)
+                | InlineToken::Strikethrough(children)
+                | InlineToken::Highlight(children)
+                | InlineToken::Superscript(children)
+                | InlineToken::Subscript(children) => InlineToken::plain_text(children),
+                InlineToken::Kbd(keys) => keys.join("+"),
+                InlineToken::Autolink(text) | InlineToken::Email(text) => text.clone(),
+                InlineToken::Link { children, .. } => InlineToken::plain_text(children),
+                InlineToken::Image { alt, .. } => alt.clone(),
+                InlineToken::SoftBreak | InlineToken::HardBreak => " ".to_string(),
+                InlineToken::Math { tex, .. } => tex.clone(),
+                InlineToken::Html(_) | InlineToken::FootnoteRef { .. } => String::new(),
+            })
+            .collect()
+    }
+
+    /// Children of tokens that only change the text style, links are left out on purpose
+    fn children_mut(&mut self) -> Option<&mut Vec<InlineToken>> {
+        match self {
+            InlineToken::Emphasis(children)
+            | InlineToken::Strong(children)
+            | InlineToken::Strikethrough(children)
+            | InlineToken::Highlight(children)
+            | InlineToken::Superscript(children)
+            | InlineToken::Subscript(children) => Some(children),
+            _ => None,
+        }
+    }
+
+    /// Id of a footnote reference, the footnote links back to it
+    pub fn footnote_ref_id(number: usize, occurrence: usize) -> String {
+        match occurrence {
+            1 => format!("fnref-{}", number),
+            _ => format!("fnref-{}-{}", number, occurrence),
+        }
+    }
+
+    pub fn to_html(&self) -> String {
+        match self {
+            InlineToken::Text(text) => escape_html(text),
+            InlineToken::Code(code) => {
+                format!("<span class='inline-code'>{}</span>", escape_html(code))
+            }
+            InlineToken::Emphasis(children) => {
+                format!("<em>{}</em>", InlineToken::render(children))
+            }
+            InlineToken::Strong(children) => {
+                format!("<strong>{}</strong>", InlineToken::render(children))
+            }
+            InlineToken::Strikethrough(children) => {
+                format!("<del>{}</del>", InlineToken::render(children))
+            }
+            InlineToken::Highlight(children) => {
+                format!("<mark>{}</mark>", InlineToken::render(children))
+            }
+            InlineToken::Superscript(children) => {
+                format!("<sup>{}</sup>", InlineToken::render(children))
+            }
+            InlineToken::Subscript(children) => {
+                format!("<sub>{}</sub>", InlineToken::render(children))
+            }
+            InlineToken::Kbd(keys) => keys
+                .iter()
+                .map(|key| format!("<kbd>{}</kbd>", escape_html(key)))
+                .collect::<Vec<String>>()
+                .join("+"),
+            InlineToken::Link {
+                href,
+                title,
+                children,
+            } => format!(
+                "<a href='{}'{}>{}</a>",
+                escape_html(href),
+                title_attribute(title),
+                InlineToken::render(children)
+            ),
+            InlineToken::Autolink(url) => {
+                let href = if url.starts_with("www.") {
+                    format!("http://{}", url)
+                } else {
+                    url.clone()
+                };
+                format!("<a href='{}'>{}</a>", escape_html(&href), escape_html(url))
+            }
+            InlineToken::Email(email) => format!(
+                "<a href='mailto:{}'>{}</a>",
+                escape_html(email),
+                escape_html(email)
+            ),
+            InlineToken::Image {
+                src,
+                alt,
+                title,
+                size,
+            } => format!(
+                "<img src='{}' alt='{}'{}{} loading='lazy'/>",
+                escape_html(src),
+                escape_html(alt),
+                title_attribute(title),
+                size.to_html()
+            ),
+            InlineToken::FootnoteRef { number, occurrence } => format!(
+                "<sup class='footnote-ref' id='{}'><a href='#fn-{}'>{}</a></sup>",
+                InlineToken::footnote_ref_id(*number, *occurrence),
+                number,
+                number
+            ),
+            InlineToken::Html(html) => html.clone(),
+            InlineToken::Math { mathml, .. } => mathml.clone(),
+            InlineToken::SoftBreak => "\n".to_string(),
+            InlineToken::HardBreak => "<br/>\n".to_string(),
+        }
+    }
+}
+
+/// Optional `{width=300 height=200}` suffix right after an image
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImageSize {
+    width: Option<String>,
+    height: Option<String>,
+}
+
+impl ImageSize {
+    /// Parse the suffix at the start of `text`, returns the size and the suffix length
+    fn new(text: &str) -> Option<(ImageSize, usize)> {
+        static RE_SUFFIX: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^\{(?P<attrs>[^{}\n]*)\}").unwrap());
+        static RE_ATTR: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+            r#"^(?P<name>width|height)=(?:"(?P<dq>[0-9.]+(?:px|%|em|rem)?)"|'(?P<sq>[0-9.]+(?:px|%|em|rem)?)'|(?P<value>[0-9.]+(?:px|%|em|rem)?))$"#,
+        )
+        .unwrap()
+        });
+
+        let caps = RE_SUFFIX.captures(text)?;
+        let mut size = ImageSize::default();
+
+        // Every attribute must be a valid width or height, otherwise it's just text
+        for attr in caps["attrs"].split_whitespace() {
+            let attr = RE_ATTR.captures(attr)?;
+            let value = attr
+                .name("dq")
+                .or_else(|| attr.name("sq"))
+                .or_else(|| attr.name("value"))?
+                .as_str()
+                .to_string();
+
+            match &attr["name"] {
+                "width" => size.width = Some(value),
+                _ => size.height = Some(value),
+            }
+        }
+
+        Some((size, caps[0].len()))
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = String::new();
+        if let Some(width) = &self.width {
+            html.push_str(&format!(" width='{}'", escape_html(width)));
+        }
+        if let Some(height) = &self.height {
+            html.push_str(&format!(" height='{}'", escape_html(height)));
+        }
+        html
+    }
+}
+
+fn title_attribute(title: &Option<String>) -> String {
+    match title {
+        Some(title) => format!(" title='{}'", escape_html(title)),
+        None => String::new(),
+    }
+}
+
+/// A run of `*`, `_`, `~`, `=` or `^` that may open or close emphasis
+#[derive(Debug)]
+struct Delimiter {
+    ch: char,
+    count: usize,
+    /// Length of the run before any of it was used, needed for the "rule of 3"
+    original: usize,
+    can_open: bool,
+    can_close: bool,
+}
+
+impl Delimiter {
+    /// See https://spec.commonmark.org/0.30/#can-open-emphasis
+    fn new(ch: char, count: usize, before: Option<char>, after: Option<char>) -> Self {
+        let is_space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+        let is_punctuation = |c: Option<char>| c.is_some_and(is_punctuation);
+
+        let left_flanking = !is_space(after)
+            && (!is_punctuation(after) || is_space(before) || is_punctuation(before));
+        let right_flanking = !is_space(before)
+            && (!is_punctuation(before) || is_space(after) || is_punctuation(after));
+
+        // `_` can't open or close emphasis inside words like `snake_case_names`
+        let (can_open, can_close) = match ch {
+            '_' => (
+                left_flanking && (!right_flanking || is_punctuation(before)),
+                right_flanking && (!left_flanking || is_punctuation(after)),
+            ),
+            _ => (left_flanking, right_flanking),
+        };
+
+        Delimiter {
+            ch,
+            count,
+            original: count,
+            can_open,
+            can_close,
+        }
+    }
+
+    fn can_pair(opener: &Delimiter, closer: &Delimiter) -> bool {
+        if opener.ch != closer.ch || !opener.can_open {
+            return false;
+        }
+
+        // Extensions only pair with a run of the exact same length
+        if !matches!(opener.ch, '*' | '_') {
+            return opener.count == closer.count;
+        }
+
+        // Rule of 3: `*foo**bar*` shouldn't be parsed as `<em>foo</em><em>bar</em>`
+        let both_ways = opener.can_close || closer.can_open;
+        let sum = opener.original + closer.original;
+        !(both_ways
+            && sum.is_multiple_of(3)
+            && !(opener.original.is_multiple_of(3) && closer.original.is_multiple_of(3)))
+    }
+
+    fn into_text(self) -> InlineToken {
+        InlineToken::Text(self.ch.to_string().repeat(self.count))
+    }
+}
+
+/// Intermediate result of the scan, delimiters and brackets are resolved into tokens later
+#[derive(Debug)]
+enum Piece {
+    Token(InlineToken),
+    Delimiter(Delimiter),
+    /// `[` or `![` waiting for its `]`, links inside links are not allowed so it can be deactivated
+    Bracket {
+        image: bool,
+        active: bool,
+        /// Where the bracket content starts, used as label by reference links
+        start: usize,
+    },
+}
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    /// Line of the page where the text starts, to report problems
+    line: usize,
+    pos: usize,
+    pieces: Vec<Piece>,
+    buffer: String,
+    ctx: &'a Context<'a>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str, line: usize, ctx: &'a Context<'a>) -> Self {
+        Tokenizer {
+            text,
+            line,
+            pos: 0,
+            pieces: vec![],
+            buffer: String::new(),
+            ctx,
+        }
+    }
+
+    fn tokenize(mut self) -> Vec<InlineToken> {
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => self.backslash(),
+                '`' => self.code_span(),
+                '$' if self.ctx.config.extensions.math => self.math(),
+                '*' | '_' | '~' | '=' | '^' => self.delimiter_run(c),
+                '[' if self.ctx.config.extensions.kbd
+                    && self.text[self.pos..].starts_with("[[") =>
+                {
+                    self.kbd()
+                }
+                '[' if self.text[self.pos..].starts_with("[^") => self.footnote_ref(),
+                '[' => self.open_bracket(false, 1),
+                '!' if self.text[self.pos + 1..].starts_with('[') => self.open_bracket(true, 2),
+                ']' => self.close_bracket(),
+                '<' => self.angle_bracket(),
+                '\n' => self.line_break(),
+                _ => {
+                    self.buffer.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+
+        self.flush();
+        let mut tokens = resolve_emphasis(self.pieces);
+        if self.ctx.config.extensions.autolink {
+            linkify(&mut tokens);
+        }
+        let typography = &self.ctx.config.typography;
+        if typography.emoji || typography.smart_punctuation {
+            Typographer::new(typography).apply(&mut tokens);
+        }
+        tokens
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn char_before(&self, pos: usize) -> Option<char> {
+        self.text[..pos].chars().next_back()
+    }
+
+    /// Move any pending text into its own token
+    fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            let text = std::mem::take(&mut self.buffer);
+            self.pieces.push(Piece::Token(InlineToken::Text(text)));
+        }
+    }
+
+    fn push(&mut self, piece: Piece) {
+        self.flush();
+        self.pieces.push(piece);
+    }
+
+    /// Escaped punctuation is kept as literal text, a backslash at the end of a line is a hard break
+    fn backslash(&mut self) {
+        self.pos += 1;
+        match self.peek() {
+            Some('\n') => {
+                self.pos += 1;
+                self.push(Piece::Token(InlineToken::HardBreak));
+            }
+            Some(c) if c.is_ascii_punctuation() => {
+                self.buffer.push(c);
+                self.pos += 1;
+            }
+            _ => self.buffer.push('\\'),
+        }
+    }
+
+    /// A backtick string and everything up to the next backtick string of the same length
+    fn code_span(&mut self) {
+        let rest = &self.text[self.pos..];
+        let size = rest.len() - rest.trim_start_matches('`').len();
+        let content_start = self.pos + size;
+
+        let mut search = content_start;
+        while let Some(offset) = self.text[search..].find('`') {
+            let start = search + offset;
+            let run = &self.text[start..];
+            let run_size = run.len() - run.trim_start_matches('`').len();
+
+            if run_size == size {
+                let code = self.text[content_start..start].replace('\n', " ");
+                // One space on both sides is stripped so code can start or end with backticks
+                let code =
+                    if code.starts_with(' ') && code.ends_with(' ') && !code.trim().is_empty() {
+                        code[1..code.len() - 1].to_string()
+                    } else {
+                        code
+                    };
+
+                self.push(Piece::Token(InlineToken::Code(code)));
+                self.pos = start + size;
+                return;
+            }
+            search = start + run_size;
+        }
+
+        // No closing backticks, treated as literal text
+        self.buffer.push_str(&"`".repeat(size));
+        self.pos = content_start;
+    }
+
+    /// `$inline$` or `$$display$$` TeX up to the next unescaped dollars, inline math can't start or
+    /// end with a space and can't be followed by a digit so prices like `$5 and $10` stay text
+    fn math(&mut self) {
+        let display = self.text[self.pos..].starts_with("$$");
+        let delimiter = if display { "$$" } else { "$" };
+        let content_start = self.pos + delimiter.len();
+
+        let mut end = content_start;
+        while let Some(offset) = self.text[end..].find(delimiter) {
+            end += offset;
+            if !self.text[..end].ends_with('\\') {
+                break;
+            }
+            end += 1;
+        }
+
+        let tex = &self.text[content_start..end];
+        let after = self.text[end..].chars().nth(delimiter.len());
+        let is_math = self.text[end..].starts_with(delimiter)
+            && !tex.trim().is_empty()
+            && (display
+                || !tex.starts_with(char::is_whitespace)
+                    && !tex.ends_with(char::is_whitespace)
+                    && !after.is_some_and(|c| c.is_ascii_digit()));
+
+        if !is_math {
+            self.buffer.push_str(delimiter);
+            self.pos = content_start;
+            return;
+        }
+
+        let math = Math::new(tex, display);
+        for (offset, message) in &math.errors {
+            let line = self.text[..content_start + offset].matches('\n').count();
+            self.ctx.warn(self.line + line, message);
+        }
+
+        self.push(Piece::Token(InlineToken::Math {
+            tex: tex.to_string(),
+            mathml: math.mathml,
+        }));
+        self.pos = end + delimiter.len();
+    }
+
+    fn delimiter_run(&mut self, ch: char) {
+        let rest = &self.text[self.pos..];
+        let count = rest.len() - rest.trim_start_matches(ch).len();
+        let before = self.char_before(self.pos);
+        let after = rest[count..].chars().next();
+
+        let extensions = &self.ctx.config.extensions;
+        let enabled = match (ch, count) {
+            ('*' | '_', _) => true,
+            ('~', 1) => extensions.subscript,
+            ('~', 2) => extensions.strikethrough,
+            ('=', 2) => extensions.highlight,
+            ('^', 1) => extensions.superscript,
+            _ => false,
+        };
+
+        if enabled {
+            self.push(Piece::Delimiter(Delimiter::new(ch, count, before, after)));
+        } else {
+            self.buffer.push_str(&rest[..count]);
+        }
+        self.pos += count;
+    }
+
+    /// Keyboard shortcut like `[[Ctrl+Shift+P]]`, a `+` right after a separator is a key itself
+    fn kbd(&mut self) {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^\[\[(?P<keys>[^\[\]\n]+)\]\]").unwrap());
+        let caps = match RE.captures(&self.text[self.pos..]) {
+            Some(caps) => caps,
+            None => return self.open_bracket(false, 1),
+        };
+
+        let mut keys = vec![];
+        let mut key = String::new();
+        for c in caps["keys"].trim().chars() {
+            if c == '+' && !key.trim().is_empty() {
+                keys.push(key.trim().to_string());
+                key.clear();
+            } else {
+                key.push(c);
+            }
+        }
+        if !key.trim().is_empty() {
+            keys.push(key.trim().to_string());
+        }
+
+        self.pos += caps[0].len();
+        self.push(Piece::Token(InlineToken::Kbd(keys)));
+    }
+
+    /// `[^label]`, only when the footnote is defined otherwise it's a regular bracket
+    fn footnote_ref(&mut self) {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^\[\^(?P<label>[^\]\s]+)\]").unwrap());
+        let found = RE
+            .captures(&self.text[self.pos..])
+            .and_then(|caps| Some((caps[0].len(), self.ctx.footnote_ref(&caps["label"])?)));
+
+        match found {
+            Some((len, (number, occurrence))) => {
+                self.pos += len;
+                self.push(Piece::Token(InlineToken::FootnoteRef {
+                    number,
+                    occurrence,
+                }));
+            }
+            None => self.open_bracket(false, 1),
+        }
+    }
+
+    fn open_bracket(&mut self, image: bool, size: usize) {
+        self.push(Piece::Bracket {
+            image,
+            active: true,
+            start: self.pos + size,
+        });
+        self.pos += size;
+    }
+
+    fn close_bracket(&mut self) {
+        let label_end = self.pos;
+        self.pos += 1;
+        self.flush();
+
+        let opener_idx = self
+            .pieces
+            .iter()
+            .rposition(|p| matches!(p, Piece::Bracket { .. }));
+
+        let (opener_idx, image, label_start) = match opener_idx.map(|idx| (idx, &self.pieces[idx]))
+        {
+            Some((
+                idx,
+                Piece::Bracket {
+                    image,
+                    active,
+                    start,
+                },
+            )) if *active => (idx, *image, *start),
+            Some((idx, _)) => {
+                self.bracket_into_text(idx);
+                self.buffer.push(']');
+                return;
+            }
+            None => {
+                self.buffer.push(']');
+                return;
+            }
+        };
+
+        let link = self
+            .inline_link(self.pos)
+            .or_else(|| self.reference_link(&self.text[label_start..label_end], self.pos));
+
+        let (mut href, title, end) = match link {
+            Some(link) => link,
+            None => {
+                self.bracket_into_text(opener_idx);
+                self.buffer.push(']');
+                return;
+            }
+        };
+        // Same schemes as the sanitized HTML tags, the text of the link is kept
+        if self.ctx.config.raw_html == RawHtml::Sanitize && !is_safe_url(&href) {
+            href.clear();
+        }
+
+        self.pos = end;
+        let children = resolve_emphasis(self.pieces.drain(opener_idx + 1..).collect());
+        self.pieces.pop();
+
+        let token = if image {
+            let size = match ImageSize::new(&self.text[self.pos..]) {
+                Some((size, len)) => {
+                    self.pos += len;
+                    size
+                }
+                None => ImageSize::default(),
+            };
+
+            InlineToken::Image {
+                src: href,
+                alt: InlineToken::plain_text(&children),
+                title,
+                size,
+            }
+        } else {
+            // Links can't contain other links
+            self.pieces.iter_mut().for_each(|p| {
+                if let Piece::Bracket {
+                    image: false,
+                    active,
+                    ..
+                } = p
+                {
+                    *active = false;
+                }
+            });
+
+            InlineToken::Link {
+                href,
+                title,
+                children,
+            }
+        };
+
+        self.pieces.push(Piece::Token(token));
+    }
+
+    fn bracket_into_text(&mut self, idx: usize) {
+        if let Piece::Bracket { image, .. } = self.pieces[idx] {
+            let text = if image { "![" } else { "[" };
+            self.pieces[idx] = Piece::Token(InlineToken::Text(text.to_string()));
+        }
+    }
+
+    /// Parse `(destination "title")` right after the closing bracket
+    fn inline_link(&self, pos: usize) -> Option<(String, Option<String>, usize)> {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+            r#"^\(\s*(?:<(?P<angled>(?:[^<>\n\\]|\\.)*)>|(?P<href>(?:[^\s()\\]|\\.|\((?:[^\s()\\]|\\.)*\))*))(?:\s+(?:"(?P<dq>(?:[^"\\]|\\.)*)"|'(?P<sq>(?:[^'\\]|\\.)*)'|\((?P<pq>(?:[^()\\]|\\.)*)\)))?\s*\)"#,
+        )
+        .unwrap()
+        });
+
+        let caps = RE.captures(&self.text[pos..])?;
+        let href = caps
+            .name("angled")
+            .or_else(|| caps.name("href"))
+            .map_or("", |m| m.as_str());
+        let title = caps
+            .name("dq")
+            .or_else(|| caps.name("sq"))
+            .or_else(|| caps.name("pq"))
+            .map(|m| unescape(m.as_str()));
+
+        Some((unescape(href), title, pos + caps[0].len()))
+    }
+
+    /// Resolve `[text][label]`, `[label][]` or `[label]` using the link definitions
+    fn reference_link(&self, text: &str, pos: usize) -> Option<(String, Option<String>, usize)> {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^\[(?P<label>(?:[^\[\]\\]|\\.)*)\]").unwrap());
+
+        let (label, end) = match RE.captures(&self.text[pos..]) {
+            Some(caps) if caps["label"].is_empty() => (text, pos + 2),
+            Some(caps) if caps["label"].trim().is_empty() => return None,
+            Some(caps) => (caps.name("label")?.as_str(), pos + caps[0].len()),
+            None => (text, pos),
+        };
+
+        let link = self.ctx.link(label)?;
+        Some((link.href.clone(), link.title.clone(), end))
+    }
+
+    /// Autolinks like `<https://example.com>` or `<me@example.com>`, then inline HTML if allowed
+    fn angle_bracket(&mut self) {
+        static RE_URI: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^<(?P<uri>[A-Za-z][A-Za-z0-9+.-]{1,31}:[^\s<>]*)>").unwrap()
+        });
+        static RE_EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+            r"^<(?P<email>[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*)>",
+        )
+        .unwrap()
+        });
+
+        let rest = &self.text[self.pos..];
+        let sanitize = self.ctx.config.raw_html == RawHtml::Sanitize;
+        if let Some(caps) = RE_URI
+            .captures(rest)
+            .filter(|caps| !sanitize || is_safe_url(&caps["uri"]))
+        {
+            self.pos += caps[0].len();
+            self.push(Piece::Token(InlineToken::Autolink(caps["uri"].to_string())));
+        } else if let Some(caps) = RE_EMAIL.captures(rest) {
+            self.pos += caps[0].len();
+            self.push(Piece::Token(InlineToken::Email(caps["email"].to_string())));
+        } else if self.ctx.config.raw_html != RawHtml::Escape {
+            self.html_tag();
+        } else {
+            self.buffer.push('<');
+            self.pos += 1;
+        }
+    }
+
+    /// Open or closing tags and comments, anything else is literal text
+    fn html_tag(&mut self) {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(&format!("^(?:{})", HTML_TAG)).unwrap());
+
+        match RE.find(&self.text[self.pos..]) {
+            Some(tag) => {
+                if self.ctx.config.raw_html == RawHtml::Sanitize {
+                    let sanitizer = Sanitizer::new(&self.ctx.config.html_allow_list);
+                    if let Some(tag) = sanitizer.clean_tag(tag.as_str()) {
+                        self.push(Piece::Token(InlineToken::Html(tag)));
+                    }
+                } else {
+                    self.push(Piece::Token(InlineToken::Html(tag.as_str().to_string())));
+                }
+                self.pos += tag.end();
+            }
+            None => {
+                self.buffer.push('<');
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Two or more trailing spaces make a hard break, other line endings are soft breaks
+    fn line_break(&mut self) {
+        let trimmed = self.buffer.trim_end_matches(' ').len();
+        let spaces = self.buffer.len() - trimmed;
+        self.buffer.truncate(trimmed);
+
+        let token = if spaces >= 2 {
+            InlineToken::HardBreak
+        } else {
+            InlineToken::SoftBreak
+        };
+        self.push(Piece::Token(token));
+        self.pos += 1;
+    }
+}
+
+/// Match emphasis openers and closers, see https://spec.commonmark.org/0.30/#process-emphasis
+fn resolve_emphasis(mut pieces: Vec<Piece>) -> Vec<InlineToken> {
+    let mut closer_idx = 0;
+    while closer_idx < pieces.len() {
+        let closer = match &pieces[closer_idx] {
+            Piece::Delimiter(d) if d.can_close => d,
+            _ => {
+                closer_idx += 1;
+                continue;
+            }
+        };
+
+        let opener_idx = (0..closer_idx).rev().find(|&idx| match &pieces[idx] {
+            Piece::Delimiter(opener) => Delimiter::can_pair(opener, closer),
+            _ => false,
+        });
+
+        let opener_idx = match opener_idx {
+            Some(idx) => idx,
+            None => {
+                // Can still be used as an opener for a later closer
+                if let Piece::Delimiter(d) = &mut pieces[closer_idx] {
+                    d.can_close = false;
+                }
+                closer_idx += 1;
+                continue;
+            }
+        };
+
+        let used = match (&pieces[opener_idx], &pieces[closer_idx]) {
+            (Piece::Delimiter(o), _) if !matches!(o.ch, '*' | '_') => o.count,
+            (Piece::Delimiter(o), Piece::Delimiter(c)) if o.count >= 2 && c.count >= 2 => 2,
+            _ => 1,
+        };
+
+        let ch = match &pieces[opener_idx] {
+            Piece::Delimiter(opener) => opener.ch,
+            _ => unreachable!(),
+        };
+
+        let children = into_tokens(pieces.drain(opener_idx + 1..closer_idx).collect());
+        let token = match (ch, used) {
+            ('~', 2) => InlineToken::Strikethrough(children),
+            ('~', _) => InlineToken::Subscript(children),
+            ('=', _) => InlineToken::Highlight(children),
+            ('^', _) => InlineToken::Superscript(children),
+            (_, 2) => InlineToken::Strong(children),
+            _ => InlineToken::Emphasis(children),
+        };
+        pieces.insert(opener_idx + 1, Piece::Token(token));
+        closer_idx = opener_idx + 2;
+
+        if let Piece::Delimiter(closer) = &mut pieces[closer_idx] {
+            closer.count -= used;
+            if closer.count == 0 {
+                pieces.remove(closer_idx);
+            }
+        }
+
+        if let Piece::Delimiter(opener) = &mut pieces[opener_idx] {
+            opener.count -= used;
+            if opener.count == 0 {
+                pieces.remove(opener_idx);
+                closer_idx -= 1;
+            }
+        }
+    }
+
+    into_tokens(pieces)
+}
+
+/// Turn bare URLs and emails into links, see https://github.github.com/gfm/#autolinks-extension-
+fn linkify(tokens: &mut Vec<InlineToken>) {
+    let mut idx = 0;
+    while idx < tokens.len() {
+        if let Some(children) = tokens[idx].children_mut() {
+            linkify(children);
+        }
+
+        let links = match &tokens[idx] {
+            InlineToken::Text(text) => split_autolinks(text),
+            _ => None,
+        };
+
+        match links {
+            Some(links) => {
+                let size = links.len();
+                tokens.splice(idx..idx + 1, links);
+                idx += size;
+            }
+            None => idx += 1,
+        }
+    }
+}
+
+/// Split text around the URLs and emails it contains, `None` if there's nothing to link
+fn split_autolinks(text: &str) -> Option<Vec<InlineToken>> {
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+        r"(?P<url>(?:https?://|www\.)[A-Za-z0-9_-]+(?:\.[A-Za-z0-9_-]+)*[^\s<]*)|(?P<email>[A-Za-z0-9._+-]+@[A-Za-z0-9_-]+(?:\.[A-Za-z0-9_-]+)+)",
+    )
+    .unwrap()
+    });
+
+    let mut tokens = vec![];
+    let mut last = 0;
+    for caps in RE.captures_iter(text) {
+        let m = caps.get(0).unwrap();
+        let before = text[..m.start()].chars().next_back();
+
+        let token = if caps.name("url").is_some() {
+            // Only at the start of a word, or right after an opening parenthesis
+            if before.is_some_and(|c| !c.is_whitespace() && !"*_~(".contains(c)) {
+                continue;
+            }
+            let url = trim_url(m.as_str());
+            if url.starts_with("www.") && !url[4..].contains('.') {
+                continue;
+            }
+            InlineToken::Autolink(url.to_string())
+        } else {
+            if before.is_some_and(|c| c.is_alphanumeric()) {
+                continue;
+            }
+            let email = m.as_str().trim_end_matches('.');
+            if email.ends_with(['-', '_']) {
+                continue;
+            }
+            InlineToken::Email(email.to_string())
+        };
+
+        let end = m.start() + token_len(&token);
+        if m.start() > last {
+            tokens.push(InlineToken::Text(text[last..m.start()].to_string()));
+        }
+        tokens.push(token);
+        last = end;
+    }
+
+    if tokens.is_empty() {
+        return None;
+    }
+
+    if last < text.len() {
+        tokens.push(InlineToken::Text(text[last..].to_string()));
+    }
+    Some(tokens)
+}
+
+fn token_len(token: &InlineToken) -> usize {
+    match token {
+        InlineToken::Autolink(text) | InlineToken::Email(text) => text.len(),
+        _ => 0,
+    }
+}
+
+/// Trailing punctuation and unbalanced parentheses are not part of a bare URL
+fn trim_url(mut url: &str) -> &str {
+    static RE_ENTITY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&[A-Za-z0-9]+;$").unwrap());
+    loop {
+        let unbalanced = url.ends_with(')') && url.matches('(').count() < url.matches(')').count();
+        let trimmed =
+            if unbalanced || url.ends_with(['?', '!', '.', ',', ':', '*', '_', '~', '\'', '"']) {
+                &url[..url.len() - 1]
+            } else if let Some(entity) = RE_ENTITY.find(url) {
+                &url[..entity.start()]
+            } else {
+                return url;
+            };
+        url = trimmed;
+    }
+}
+
+/// Unmatched delimiters and brackets become text, adjacent text tokens are merged
+fn into_tokens(pieces: Vec<Piece>) -> Vec<InlineToken> {
+    let mut tokens: Vec<InlineToken> = vec![];
+    for piece in pieces {
+        let token = match piece {
+            Piece::Token(token) => token,
+            Piece::Delimiter(delimiter) => delimiter.into_text(),
+            Piece::Bracket { image: true, .. } => InlineToken::Text("![".to_string()),
+            Piece::Bracket { image: false, .. } => InlineToken::Text("[".to_string()),
+        };
+
+        match (tokens.last_mut(), token) {
+            (Some(InlineToken::Text(last)), InlineToken::Text(text)) => last.push_str(&text),
+            (_, token) => tokens.push(token),
+        }
+    }
+
+    tokens
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || (!c.is_alphanumeric() && !c.is_whitespace() && !c.is_control())
+}
+
+/// Remove backslash escapes from link destinations and titles
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&next) if c == '\\' && next.is_ascii_punctuation() => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::InlineToken;
+    use crate::{
+        config::{Config, Link, RawHtml},
+        context::Context,
+    };
+
+    fn to_html(text: &str) -> String {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        InlineToken::render(&InlineToken::parse(text, 1, &ctx))
+    }
+
+    #[test]
+    fn it_parse_nested_emphasis_and_links() {
+        assert_eq!(
+            to_html("**[link](x)** and *__both__*"),
+            "<strong><a href='x'>link</a></strong> and <em><strong>both</strong></em>"
+        );
+        assert_eq!(
+            to_html("[**bold** _it_](/a \"Title\")"),
+            "<a href='/a' title='Title'><strong>bold</strong> <em>it</em></a>"
+        );
+        assert_eq!(to_html("***a***"), "<em><strong>a</strong></em>");
+        assert_eq!(to_html("*a **b** c*"), "<em>a <strong>b</strong> c</em>");
+    }
+
+    #[test]
+    fn it_follow_flanking_rules() {
+        assert_eq!(to_html("snake_case_names"), "snake_case_names");
+        assert_eq!(to_html("a * b * c"), "a * b * c");
+        assert_eq!(to_html("**unclosed"), "**unclosed");
+        assert_eq!(to_html("in*word*emphasis"), "in<em>word</em>emphasis");
+        assert_eq!(to_html("_not_closed_here"), "_not_closed_here");
+    }
+
+    #[test]
+    fn it_keep_repeated_and_literal_text() {
+        assert_eq!(
+            to_html("`a` and `a` then <$1>"),
+            "<span class='inline-code'>a</span> and <span class='inline-code'>a</span> then &lt;$1&gt;"
+        );
+        assert_eq!(to_html("\\*not\\* \\[x\\]"), "*not* [x]");
+        assert_eq!(
+            to_html("`` a`b `` `*x*`"),
+            "<span class='inline-code'>a`b</span> <span class='inline-code'>*x*</span>"
+        );
+        assert_eq!(to_html("[not a link] (x)"), "[not a link] (x)");
+    }
+
+    #[test]
+    fn it_parse_image_title_size_and_links() {
+        assert_eq!(
+            to_html("![](empty.png) ![a](b.png \"The 'title'\")"),
+            "<img src='empty.png' alt='' loading='lazy'/> <img src='b.png' alt='a' title='The &#39;title&#39;' loading='lazy'/>"
+        );
+        assert_eq!(
+            to_html("[![logo](logo.svg)](https://example.com)"),
+            "<a href='https://example.com'><img src='logo.svg' alt='logo' loading='lazy'/></a>"
+        );
+        assert_eq!(
+            to_html("![a](b.png){width=300 height=\"50%\"} ![c](d.png){.x}"),
+            "<img src='b.png' alt='a' width='300' height='50%' loading='lazy'/> <img src='d.png' alt='c' loading='lazy'/>{.x}"
+        );
+    }
+
+    #[test]
+    fn it_parse_extension_syntax() {
+        assert_eq!(
+            to_html("~~old~~ ==new== 2^10^ H~2~O ~~~no~~~"),
+            "<del>old</del> <mark>new</mark> 2<sup>10</sup> H<sub>2</sub>O ~~~no~~~"
+        );
+        assert_eq!(
+            to_html("[[Ctrl+Shift+P]] [[Ctrl++]] a == b"),
+            "<kbd>Ctrl</kbd>+<kbd>Shift</kbd>+<kbd>P</kbd> <kbd>Ctrl</kbd>+<kbd>+</kbd> a == b"
+        );
+
+        let mut config = Config::new();
+        config.extensions.subscript = false;
+        config.extensions.kbd = false;
+        let ctx = Context::new(&config, "");
+        assert_eq!(
+            InlineToken::render(&InlineToken::parse("~~a~~ ~b~ [[c]]", 1, &ctx)),
+            "<del>a</del> ~b~ [[c]]"
+        );
+    }
+
+    #[test]
+    fn it_detect_autolinks() {
+        assert_eq!(
+            to_html("<https://a.com/x?y=1&z> <me@mail.com> <not a link>"),
+            "<a href='https://a.com/x?y=1&amp;z'>https://a.com/x?y=1&amp;z</a> <a href='mailto:me@mail.com'>me@mail.com</a> &lt;not a link&gt;"
+        );
+        assert_eq!(
+            to_html("See https://example.com/path_(x)), www.rust-lang.org. or ask me@x.org!"),
+            "See <a href='https://example.com/path_(x)'>https://example.com/path_(x)</a>), <a href='http://www.rust-lang.org'>www.rust-lang.org</a>. or ask <a href='mailto:me@x.org'>me@x.org</a>!"
+        );
+        assert_eq!(
+            to_html("`https://a.com` [https://b.com](https://c.com) *https://d.com*"),
+            "<span class='inline-code'>https://a.com</span> <a href='https://c.com'>https://b.com</a> <em><a href='https://d.com'>https://d.com</a></em>"
+        );
+    }
+
+    #[test]
+    fn it_drop_unsafe_urls_when_sanitizing() {
+        let mut config = Config::new();
+        config.raw_html = RawHtml::Sanitize;
+        config.links.insert(
+            "bad".to_string(),
+            Link {
+                href: "JavaScript:alert(1)".to_string(),
+                title: None,
+            },
+        );
+        let ctx = Context::new(&config, "");
+        let html = |text: &str| InlineToken::render(&InlineToken::parse(text, 1, &ctx));
+
+        assert_eq!(
+            html("[x](javascript:alert(1)) ![i](javascript:alert(1)) [y][bad]"),
+            "<a href=''>x</a> <img src='' alt='i' loading='lazy'/> <a href=''>y</a>"
+        );
+        assert_eq!(html("<javascript:alert(1)>"), "&lt;javascript:alert(1)&gt;");
+        assert_eq!(
+            html("[ok](https://a.com) [rel](../b.md#c) <mailto:me@a.com>"),
+            "<a href='https://a.com'>ok</a> <a href='../b.md#c'>rel</a> <a href='mailto:me@a.com'>mailto:me@a.com</a>"
+        );
+    }
+
+    #[test]
+    fn it_resolve_reference_links() {
+        let mut config = Config::new();
+        config.links.insert(
+            "Rust".to_string(),
+            Link {
+                href: "https://rust-lang.org".to_string(),
+                title: Some("Rust".to_string()),
+            },
+        );
+        let mut ctx = Context::new(&config, "");
+        ctx.add_link(
+            "the  LOGO",
+            Link {
+                href: "logo.png".to_string(),
+                title: None,
+            },
+        );
+
+        assert_eq!(
+            InlineToken::render(&InlineToken::parse(
+                "[docs][rust], [rust][], [RUST] and ![alt][the logo] but not [missing] or [a][missing]",
+                1,
+                &ctx
+            )),
+            "<a href='https://rust-lang.org' title='Rust'>docs</a>, <a href='https://rust-lang.org' title='Rust'>rust</a>, \
+            <a href='https://rust-lang.org' title='Rust'>RUST</a> and <img src='logo.png' alt='alt' loading='lazy'/> \
+            but not [missing] or [a][missing]"
+        );
+    }
+
+    #[test]
+    fn it_parse_images_and_breaks() {
+        assert_eq!(
+            to_html("![*alt* text](a.png) end  \nnext\\\nlast\nline"),
+            "<img src='a.png' alt='alt text' loading='lazy'/> end<br/>\nnext<br/>\nlast\nline"
+        );
+        assert_eq!(to_html("[a [b](x) c](y)"), "[a <a href='x'>b</a> c](y)");
+    }
+}
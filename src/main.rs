@@ -1,9 +1,17 @@
 mod book;
 mod cli;
 mod config;
+mod context;
+mod diagrams;
 mod documents;
+mod front_matter;
+mod highlight;
+mod inline;
+mod math;
 mod node;
+mod sanitize;
 mod tokens;
+mod typography;
 mod utils;
 
 use book::Book;
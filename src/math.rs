@@ -0,0 +1,973 @@
+use crate::utils::escape_html;
+
+/// Greek letters, identifiers and symbols written as a single command
+const IDENTIFIERS: [(&str, &str); 49] = [
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ϵ"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "ϑ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("varpi", "ϖ"),
+    ("rho", "ρ"),
+    ("varrho", "ϱ"),
+    ("sigma", "σ"),
+    ("varsigma", "ς"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "ϕ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Upsilon", "Υ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("emptyset", "∅"),
+    ("varnothing", "∅"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("aleph", "ℵ"),
+    ("Re", "ℜ"),
+];
+
+const OPERATORS: [(&str, &str); 75] = [
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("cdot", "⋅"),
+    ("ast", "∗"),
+    ("star", "⋆"),
+    ("circ", "∘"),
+    ("bullet", "∙"),
+    ("oplus", "⊕"),
+    ("otimes", "⊗"),
+    ("leq", "≤"),
+    ("le", "≤"),
+    ("geq", "≥"),
+    ("ge", "≥"),
+    ("neq", "≠"),
+    ("ne", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("cong", "≅"),
+    ("propto", "∝"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("ni", "∋"),
+    ("subset", "⊂"),
+    ("supset", "⊃"),
+    ("subseteq", "⊆"),
+    ("supseteq", "⊇"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("setminus", "∖"),
+    ("wedge", "∧"),
+    ("land", "∧"),
+    ("vee", "∨"),
+    ("lor", "∨"),
+    ("neg", "¬"),
+    ("lnot", "¬"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("gets", "←"),
+    ("leftrightarrow", "↔"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"),
+    ("implies", "⟹"),
+    ("iff", "⟺"),
+    ("mapsto", "↦"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("cdots", "⋯"),
+    ("vdots", "⋮"),
+    ("ddots", "⋱"),
+    ("mid", "∣"),
+    ("parallel", "∥"),
+    ("perp", "⊥"),
+    ("angle", "∠"),
+    ("prime", "′"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("vert", "|"),
+    ("Vert", "‖"),
+    ("{", "{"),
+    ("}", "}"),
+    ("|", "‖"),
+];
+
+/// Operators with limits drawn above and below in display math
+const LARGE_OPERATORS: [(&str, &str, bool); 12] = [
+    ("sum", "∑", true),
+    ("prod", "∏", true),
+    ("coprod", "∐", true),
+    ("bigcup", "⋃", true),
+    ("bigcap", "⋂", true),
+    ("bigoplus", "⨁", true),
+    ("bigotimes", "⨂", true),
+    ("int", "∫", false),
+    ("iint", "∬", false),
+    ("iiint", "∭", false),
+    ("oint", "∮", false),
+    ("smallint", "∫", false),
+];
+
+/// Upright function names, the last ones take limits like large operators
+const FUNCTIONS: [&str; 32] = [
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "arg", "deg", "dim", "ker", "hom", "lim", "liminf", "limsup",
+    "max", "min", "sup", "inf", "det", "gcd", "Pr",
+];
+const LIMIT_FUNCTIONS: usize = 10;
+
+/// Accent, character and whether it goes over the base
+const ACCENTS: [(&str, &str, bool); 15] = [
+    ("hat", "^", true),
+    ("widehat", "^", true),
+    ("check", "ˇ", true),
+    ("tilde", "~", true),
+    ("widetilde", "~", true),
+    ("bar", "‾", true),
+    ("overline", "‾", true),
+    ("vec", "→", true),
+    ("overrightarrow", "→", true),
+    ("dot", "˙", true),
+    ("ddot", "¨", true),
+    ("acute", "´", true),
+    ("grave", "`", true),
+    ("underline", "_", false),
+    ("underbrace", "⏟", false),
+];
+
+const SPACES: [(&str, &str); 10] = [
+    (",", "0.1667em"),
+    ("thinspace", "0.1667em"),
+    (":", "0.2222em"),
+    (">", "0.2222em"),
+    (";", "0.2778em"),
+    ("!", "-0.1667em"),
+    (" ", "0.25em"),
+    ("enspace", "0.5em"),
+    ("quad", "1em"),
+    ("qquad", "2em"),
+];
+
+const DELIMITER_SIZES: [(&str, &str); 4] = [
+    ("big", "1.2em"),
+    ("Big", "1.623em"),
+    ("bigg", "2.047em"),
+    ("Bigg", "2.470em"),
+];
+
+/// Environments written as a table, with their delimiters and column alignment
+const ENVIRONMENTS: [(&str, &str, &str, &str); 15] = [
+    ("matrix", "", "", "center"),
+    ("smallmatrix", "", "", "center"),
+    ("pmatrix", "(", ")", "center"),
+    ("bmatrix", "[", "]", "center"),
+    ("Bmatrix", "{", "}", "center"),
+    ("vmatrix", "|", "|", "center"),
+    ("Vmatrix", "‖", "‖", "center"),
+    ("cases", "{", "", "left"),
+    ("array", "", "", "center"),
+    ("aligned", "", "", "right left"),
+    ("align", "", "", "right left"),
+    ("align*", "", "", "right left"),
+    ("split", "", "", "right left"),
+    ("gathered", "", "", "center"),
+    ("gather*", "", "", "center"),
+];
+
+/// Alphabet used by `\mathbf`, `\mathbb`...
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variant {
+    Normal,
+    Italic,
+    Bold,
+    BoldItalic,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+impl Variant {
+    fn new(command: &str) -> Option<Self> {
+        match command {
+            "mathrm" => Some(Variant::Normal),
+            "mathit" => Some(Variant::Italic),
+            "mathbf" => Some(Variant::Bold),
+            "boldsymbol" | "bm" => Some(Variant::BoldItalic),
+            "mathbb" => Some(Variant::DoubleStruck),
+            "mathcal" | "mathscr" => Some(Variant::Script),
+            "mathfrak" => Some(Variant::Fraktur),
+            "mathsf" => Some(Variant::SansSerif),
+            "mathtt" => Some(Variant::Monospace),
+            _ => None,
+        }
+    }
+
+    /// The Mathematical Alphanumeric Symbols block has holes for letters encoded earlier
+    fn style(&self, c: char) -> char {
+        let exceptions = match self {
+            Variant::DoubleStruck => "CℂHℍNℕPℙQℚRℝZℤ",
+            Variant::Script => "BℬEℰFℱHℋIℐLℒMℳRℛeℯgℊoℴ",
+            Variant::Fraktur => "CℭHℌIℑRℜZℨ",
+            _ => "",
+        };
+        let mut pairs = exceptions.chars();
+        while let (Some(letter), Some(styled)) = (pairs.next(), pairs.next()) {
+            if letter == c {
+                return styled;
+            }
+        }
+
+        let (upper, lower, digit) = match self {
+            Variant::Bold => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+            Variant::BoldItalic => (0x1D468, 0x1D482, Some(0x1D7CE)),
+            Variant::DoubleStruck => (0x1D538, 0x1D552, Some(0x1D7D8)),
+            Variant::Script => (0x1D49C, 0x1D4B6, None),
+            Variant::Fraktur => (0x1D504, 0x1D51E, None),
+            Variant::SansSerif => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+            Variant::Monospace => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+            Variant::Normal | Variant::Italic => return c,
+        };
+        let code = match (c, digit) {
+            ('A'..='Z', _) => upper + (c as u32 - 'A' as u32),
+            ('a'..='z', _) => lower + (c as u32 - 'a' as u32),
+            ('0'..='9', Some(digit)) => digit + (c as u32 - '0' as u32),
+            _ => return c,
+        };
+        char::from_u32(code).unwrap_or(c)
+    }
+}
+
+/// TeX math converted to MathML at build time, no script is needed to display it
+#[derive(Debug)]
+pub struct Math {
+    pub mathml: String,
+    /// Unsupported commands and syntax errors with their byte offset in the TeX, they're shown in red
+    pub errors: Vec<(usize, String)>,
+}
+
+impl Math {
+    pub fn new(tex: &str, display: bool) -> Self {
+        let mut parser = Parser {
+            chars: tex.chars().collect(),
+            pos: 0,
+            display,
+            variant: None,
+            groups: 0,
+            lefts: 0,
+            environments: 0,
+            errors: vec![],
+        };
+        let body = parser.body();
+
+        let mathml = format!(
+            "<math{}><semantics>{}<annotation encoding='application/x-tex'>{}</annotation></semantics></math>",
+            if display { " display='block'" } else { "" },
+            body,
+            escape_html(tex.trim())
+        );
+        Math {
+            mathml,
+            errors: parser.errors,
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+    /// Alphabet of the letters in the current `\mathbf{...}` like group
+    variant: Option<Variant>,
+    /// Open `{`, `\left` and `\begin`, so unbalanced closings can be reported
+    groups: usize,
+    lefts: usize,
+    environments: usize,
+    errors: Vec<(usize, String)>,
+}
+
+impl Parser {
+    /// Whole formula, `\\` breaks lines
+    fn body(&mut self) -> String {
+        let rows = self.cells();
+        if rows.len() == 1 && rows[0].len() == 1 {
+            return rows[0][0].clone();
+        }
+        Parser::table(&rows, "center")
+    }
+
+    /// Rows of `&` separated cells, up to the end of the enclosing group or environment
+    fn cells(&mut self) -> Vec<Vec<String>> {
+        let mut rows = vec![];
+        let mut row = vec![];
+        loop {
+            let cell = self.row();
+            row.push(mrow(&cell));
+
+            if self.peek() == Some('&') {
+                self.pos += 1;
+            } else if self.at_newline() {
+                self.pos += 2;
+                self.optional_argument();
+                rows.push(std::mem::take(&mut row));
+            } else {
+                rows.push(row);
+                break;
+            }
+        }
+
+        // `\\` after the last row doesn't add an empty one
+        if rows.len() > 1
+            && rows
+                .last()
+                .is_some_and(|r| r.len() == 1 && r[0] == "<mrow></mrow>")
+        {
+            rows.pop();
+        }
+        rows
+    }
+
+    /// Sequence of atoms up to a closing `}`, `&`, `\\`, `\right` or `\end`
+    fn row(&mut self) -> Vec<String> {
+        let mut atoms = vec![];
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                None => break,
+                Some('&') => break,
+                Some('}') if self.groups > 0 => break,
+                Some('}') => {
+                    self.error("unbalanced `}`".to_string());
+                    self.pos += 1;
+                }
+                Some('\\') if self.at_newline() => break,
+                Some('\\') if self.at_command("right") || self.at_command("middle") => {
+                    if self.lefts > 0 {
+                        break;
+                    }
+                    let command = self.command();
+                    self.error(format!("`\\{}` without `\\left`", command));
+                    self.delimiter();
+                }
+                Some('\\') if self.at_command("end") => {
+                    if self.environments > 0 {
+                        break;
+                    }
+                    self.command();
+                    let name = self.raw_argument();
+                    self.error(format!("`\\end{{{}}}` without `\\begin`", name));
+                }
+                Some(_) => {
+                    if let Some(atom) = self.atom() {
+                        atoms.push(atom);
+                    }
+                }
+            }
+        }
+        atoms
+    }
+
+    /// Base with its subscript and superscript
+    fn atom(&mut self) -> Option<String> {
+        let (base, mut limits) = self.base(false)?;
+
+        let mut sub: Option<String> = None;
+        let mut sup: Option<String> = None;
+        loop {
+            self.skip_spaces();
+            if self.at_command("limits") || self.at_command("nolimits") {
+                limits = self.command() == "limits";
+                continue;
+            }
+
+            match self.peek() {
+                Some('_') => {
+                    self.pos += 1;
+                    if sub.is_some() {
+                        self.error("double subscript".to_string());
+                    }
+                    sub = Some(self.argument());
+                }
+                Some('^') => {
+                    self.pos += 1;
+                    let argument = self.argument();
+                    sup = match sup {
+                        // Primes are superscripts too, `f'^2` keeps both
+                        Some(primes) if primes.starts_with("<mo>′") => {
+                            Some(format!("<mrow>{}{}</mrow>", primes, argument))
+                        }
+                        Some(_) => {
+                            self.error("double superscript".to_string());
+                            Some(argument)
+                        }
+                        None => Some(argument),
+                    };
+                }
+                Some('\'') if sup.is_none() => {
+                    let mut primes = 0;
+                    while self.peek() == Some('\'') {
+                        primes += 1;
+                        self.pos += 1;
+                    }
+                    sup = Some(format!("<mo>{}</mo>", "′".repeat(primes)));
+                }
+                _ => break,
+            }
+        }
+
+        let (sub_tag, sup_tag, both_tag) = if limits {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        let atom = match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", sub_tag, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", sup_tag, base, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both_tag, base, sub, sup),
+        };
+        Some(atom)
+    }
+
+    /// Single element and whether it takes limits, `single` only reads one digit for `x^23`
+    fn base(&mut self, single: bool) -> Option<(String, bool)> {
+        self.skip_spaces();
+        let c = self.peek()?;
+        let element = match c {
+            '{' => self.group(),
+            '\\' => return self.command_element(),
+            '%' => {
+                // Comment up to the end of the line
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+                return None;
+            }
+            '^' | '_' => "<mrow></mrow>".to_string(),
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(c) = self.peek() {
+                    let is_decimal = c == '.'
+                        && !number.contains('.')
+                        && self
+                            .chars
+                            .get(self.pos + 1)
+                            .is_some_and(|c| c.is_ascii_digit());
+                    if !(c.is_ascii_digit() || is_decimal) || (single && !number.is_empty()) {
+                        break;
+                    }
+                    number.push(self.variant.map_or(c, |v| v.style(c)));
+                    self.pos += 1;
+                }
+                if number.is_empty() {
+                    self.pos += 1;
+                    "<mo>.</mo>".to_string()
+                } else {
+                    format!("<mn>{}</mn>", number)
+                }
+            }
+            'a'..='z' | 'A'..='Z' => {
+                self.pos += 1;
+                match self.variant {
+                    Some(Variant::Normal) => format!("<mi mathvariant='normal'>{}</mi>", c),
+                    Some(variant) => format!("<mi>{}</mi>", variant.style(c)),
+                    None => format!("<mi>{}</mi>", c),
+                }
+            }
+            '~' => {
+                self.pos += 1;
+                "<mtext>&#160;</mtext>".to_string()
+            }
+            '#' | '$' => {
+                self.pos += 1;
+                self.error(format!("unexpected `{}`", c));
+                format!("<merror><mtext>{}</mtext></merror>", c)
+            }
+            '(' | ')' | '[' | ']' | '|' => {
+                self.pos += 1;
+                format!("<mo stretchy='false'>{}</mo>", c)
+            }
+            _ if c.is_alphabetic() => {
+                self.pos += 1;
+                format!("<mi>{}</mi>", c)
+            }
+            _ => {
+                self.pos += 1;
+                let operator = match c {
+                    '-' => "−".to_string(),
+                    '*' => "∗".to_string(),
+                    '\'' => "′".to_string(),
+                    _ => escape_html(&c.to_string()),
+                };
+                format!("<mo>{}</mo>", operator)
+            }
+        };
+        Some((element, false))
+    }
+
+    fn command_element(&mut self) -> Option<(String, bool)> {
+        let command = self.command();
+        let name = command.as_str();
+
+        if let Some((_, c)) = IDENTIFIERS.iter().find(|(n, _)| *n == name) {
+            let is_upright = c.chars().next().is_some_and(|c| c.is_uppercase());
+            let element = if is_upright {
+                format!("<mi mathvariant='normal'>{}</mi>", c)
+            } else {
+                format!("<mi>{}</mi>", c)
+            };
+            return Some((element, false));
+        }
+        if let Some((_, c)) = OPERATORS.iter().find(|(n, _)| *n == name) {
+            if [
+                "{", "}", "|", "langle", "rangle", "lfloor", "rfloor", "lceil", "rceil", "vert",
+                "Vert",
+            ]
+            .contains(&name)
+            {
+                return Some((format!("<mo stretchy='false'>{}</mo>", c), false));
+            }
+            return Some((format!("<mo>{}</mo>", c), false));
+        }
+        if let Some((_, c, limits)) = LARGE_OPERATORS.iter().find(|(n, _, _)| *n == name) {
+            return Some((format!("<mo>{}</mo>", c), *limits && self.display));
+        }
+        if let Some(idx) = FUNCTIONS.iter().position(|f| *f == name) {
+            let text = match name {
+                "liminf" => "lim inf",
+                "limsup" => "lim sup",
+                _ => name,
+            };
+            let limits = idx >= FUNCTIONS.len() - LIMIT_FUNCTIONS && self.display;
+            return Some((format!("<mi>{}</mi>", text), limits));
+        }
+        if let Some((_, width)) = SPACES.iter().find(|(n, _)| *n == name) {
+            return Some((format!("<mspace width='{}'/>", width), false));
+        }
+        if let Some((_, accent, over)) = ACCENTS.iter().find(|(n, _, _)| *n == name) {
+            let base = self.argument();
+            let element = if *over {
+                format!("<mover accent='true'>{}<mo>{}</mo></mover>", base, accent)
+            } else {
+                format!(
+                    "<munder accentunder='true'>{}<mo>{}</mo></munder>",
+                    base, accent
+                )
+            };
+            return Some((element, false));
+        }
+        if let Some(variant) = Variant::new(name) {
+            let outer = self.variant.replace(variant);
+            let argument = self.argument();
+            self.variant = outer;
+            return Some((argument, false));
+        }
+        if let Some(size) = DELIMITER_SIZES
+            .iter()
+            .find(|(n, _)| name.trim_end_matches(['l', 'r', 'm']) == *n)
+            .map(|(_, size)| size)
+        {
+            let delimiter = self.delimiter();
+            return Some((
+                format!("<mo minsize='{0}' maxsize='{0}'>{1}</mo>", size, delimiter),
+                false,
+            ));
+        }
+
+        let element = match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let n = self.argument();
+                let k = self.argument();
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness='0'>{}{}</mfrac><mo>)</mo></mrow>",
+                    n, k
+                )
+            }
+            "sqrt" => match self.optional_argument() {
+                Some(index) => format!("<mroot>{}{}</mroot>", self.argument(), index),
+                None => format!("<msqrt>{}</msqrt>", self.argument()),
+            },
+            "text" | "textrm" | "textup" | "mbox" => {
+                let text = self.raw_argument().replace(' ', "\u{a0}");
+                format!("<mtext>{}</mtext>", escape_html(&text))
+            }
+            "operatorname" => {
+                let text = self.raw_argument();
+                if text.chars().count() == 1 {
+                    format!("<mi mathvariant='normal'>{}</mi>", escape_html(&text))
+                } else {
+                    format!("<mi>{}</mi>", escape_html(&text))
+                }
+            }
+            "overbrace" => format!("<mover>{}<mo>⏞</mo></mover>", self.argument()),
+            "left" => self.left_right(),
+            "begin" => self.environment(),
+            "displaystyle" | "textstyle" | "scriptstyle" => String::new(),
+            "#" | "$" | "%" | "&" | "_" => format!("<mo>{}</mo>", escape_html(name)),
+            _ => {
+                self.error(format!("unsupported command `\\{}`", name));
+                format!("<merror><mtext>\\{}</mtext></merror>", escape_html(name))
+            }
+        };
+        Some((element, false))
+    }
+
+    /// `\left( ... \middle| ... \right)` with stretchy delimiters
+    fn left_right(&mut self) -> String {
+        let open = self.delimiter();
+        self.lefts += 1;
+        let mut content = vec![format!("<mo fence='true'>{}</mo>", open)];
+        content.extend(self.row());
+
+        while self.at_command("middle") {
+            self.command();
+            let middle = self.delimiter();
+            content.push(format!("<mo stretchy='true'>{}</mo>", middle));
+            content.extend(self.row());
+        }
+
+        self.lefts -= 1;
+        if self.at_command("right") {
+            self.command();
+            let close = self.delimiter();
+            content.push(format!("<mo fence='true'>{}</mo>", close));
+        } else {
+            self.error("`\\left` without `\\right`".to_string());
+        }
+        format!("<mrow>{}</mrow>", content.concat())
+    }
+
+    /// `\begin{name} ... \end{name}` written as a table
+    fn environment(&mut self) -> String {
+        let name = self.raw_argument();
+        let Some((_, open, close, align)) = ENVIRONMENTS.iter().find(|(n, ..)| *n == name) else {
+            self.error(format!("unsupported environment `{}`", name));
+            return format!("<merror><mtext>{}</mtext></merror>", escape_html(&name));
+        };
+
+        let mut align = align.to_string();
+        if name == "array" {
+            align = self
+                .raw_argument()
+                .chars()
+                .filter_map(|c| match c {
+                    'l' => Some("left"),
+                    'c' => Some("center"),
+                    'r' => Some("right"),
+                    _ => None,
+                })
+                .collect::<Vec<&str>>()
+                .join(" ");
+        }
+
+        self.environments += 1;
+        let rows = self.cells();
+        self.environments -= 1;
+
+        if self.at_command("end") {
+            self.command();
+            let end = self.raw_argument();
+            if end != name {
+                self.error(format!(
+                    "`\\begin{{{}}}` closed by `\\end{{{}}}`",
+                    name, end
+                ));
+            }
+        } else {
+            self.error(format!("`\\begin{{{}}}` without `\\end`", name));
+        }
+
+        let table = Parser::table(&rows, &align);
+        if open.is_empty() && close.is_empty() {
+            return table;
+        }
+        format!(
+            "<mrow><mo fence='true'>{}</mo>{}<mo fence='true'>{}</mo></mrow>",
+            open, table, close
+        )
+    }
+
+    fn table(rows: &[Vec<String>], align: &str) -> String {
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let cells = row
+                    .iter()
+                    .map(|cell| format!("<mtd>{}</mtd>", cell))
+                    .collect::<String>();
+                format!("<mtr>{}</mtr>", cells)
+            })
+            .collect::<String>();
+        format!("<mtable columnalign='{}'>{}</mtable>", align, rows)
+    }
+
+    /// `{...}` group as a single row
+    fn group(&mut self) -> String {
+        self.pos += 1;
+        self.groups += 1;
+        let atoms = self.row();
+        self.groups -= 1;
+
+        if self.peek() == Some('}') {
+            self.pos += 1;
+        } else {
+            self.error("missing `}`".to_string());
+        }
+        mrow(&atoms)
+    }
+
+    /// Argument of a command or script, a group or a single element
+    fn argument(&mut self) -> String {
+        match self.base(true) {
+            Some((element, _)) => element,
+            None => {
+                self.error("missing argument".to_string());
+                "<mrow></mrow>".to_string()
+            }
+        }
+    }
+
+    /// `[...]` argument, ex: the index of `\sqrt[3]{x}`
+    fn optional_argument(&mut self) -> Option<String> {
+        self.skip_spaces();
+        if self.peek() != Some('[') {
+            return None;
+        }
+        self.pos += 1;
+
+        let mut atoms = vec![];
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(']') => {
+                    self.pos += 1;
+                    break;
+                }
+                None => {
+                    self.error("missing `]`".to_string());
+                    break;
+                }
+                Some(_) => {
+                    if let Some(atom) = self.atom() {
+                        atoms.push(atom);
+                    }
+                }
+            }
+        }
+        Some(mrow(&atoms))
+    }
+
+    /// Text of a `{...}` group, for `\text` or environment names
+    fn raw_argument(&mut self) -> String {
+        self.skip_spaces();
+        if self.peek() != Some('{') {
+            self.error("missing argument".to_string());
+            return String::new();
+        }
+        self.pos += 1;
+
+        let mut text = String::new();
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return text,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            text.push(c);
+        }
+        self.error("missing `}`".to_string());
+        text
+    }
+
+    /// Delimiter after `\left`, `\right` or `\big`, `.` stands for none
+    fn delimiter(&mut self) -> String {
+        self.skip_spaces();
+        match self.peek() {
+            Some('.') => {
+                self.pos += 1;
+                String::new()
+            }
+            Some('\\') => {
+                let command = self.command();
+                let delimiter = OPERATORS
+                    .iter()
+                    .find(|(n, _)| *n == command)
+                    .map(|(_, c)| c.to_string());
+                delimiter.unwrap_or_else(|| {
+                    self.error(format!("unsupported delimiter `\\{}`", command));
+                    String::new()
+                })
+            }
+            Some(c) => {
+                self.pos += 1;
+                escape_html(&c.to_string())
+            }
+            None => {
+                self.error("missing delimiter".to_string());
+                String::new()
+            }
+        }
+    }
+
+    /// Name of the command at the cursor, after its backslash
+    fn command(&mut self) -> String {
+        self.pos += 1;
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_alphabetic()) {
+            name.push(c);
+            self.pos += 1;
+        }
+        if name.is_empty() {
+            if let Some(c) = self.peek() {
+                name.push(c);
+                self.pos += 1;
+            }
+        }
+        name
+    }
+
+    fn at_command(&self, name: &str) -> bool {
+        let end = self.pos + 1 + name.len();
+        self.peek() == Some('\\')
+            && self.chars.len() >= end
+            && self.chars[self.pos + 1..end]
+                .iter()
+                .copied()
+                .eq(name.chars())
+            && !self.chars.get(end).is_some_and(|c| c.is_ascii_alphabetic())
+    }
+
+    fn at_newline(&self) -> bool {
+        self.peek() == Some('\\') && self.chars.get(self.pos + 1) == Some(&'\\')
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        if self.errors.iter().all(|(_, m)| *m != message) {
+            let offset = self.chars[..self.pos].iter().map(|c| c.len_utf8()).sum();
+            self.errors.push((offset, message));
+        }
+    }
+}
+
+/// Group elements so they count as a single child
+fn mrow(elements: &[String]) -> String {
+    match elements {
+        [element] => element.clone(),
+        _ => format!("<mrow>{}</mrow>", elements.concat()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Math;
+
+    fn body(math: &Math) -> &str {
+        let start = math.mathml.find("<semantics>").unwrap() + "<semantics>".len();
+        let end = math.mathml.find("<annotation").unwrap();
+        &math.mathml[start..end]
+    }
+
+    #[test]
+    fn it_convert_tex_to_mathml() {
+        let math = Math::new(r"x_i^2 + \frac{1}{\sqrt{n}} \leq 3.5", false);
+        assert!(math.errors.is_empty());
+        assert_eq!(
+            body(&math),
+            "<mrow><msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup><mo>+</mo><mfrac><mn>1</mn><msqrt><mi>n</mi></msqrt></mfrac><mo>≤</mo><mn>3.5</mn></mrow>"
+        );
+        assert!(math
+            .mathml
+            .ends_with("<annotation encoding='application/x-tex'>x_i^2 + \\frac{1}{\\sqrt{n}} \\leq 3.5</annotation></semantics></math>"));
+
+        let math = Math::new(r"\sum_{k=1}^n k = \mathbb{R}", true);
+        assert!(math.mathml.starts_with("<math display='block'>"));
+        assert_eq!(
+            body(&math),
+            "<mrow><munderover><mo>∑</mo><mrow><mi>k</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>k</mi><mo>=</mo><mi>ℝ</mi></mrow>"
+        );
+
+        let math = Math::new(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}", true);
+        assert_eq!(
+            body(&math),
+            "<mrow><mo fence='true'>(</mo><mtable columnalign='center'><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable><mo fence='true'>)</mo></mrow>"
+        );
+    }
+
+    #[test]
+    fn it_report_unsupported_commands() {
+        let math = Math::new(r"\left( \foo{x} \right. + }", false);
+        assert_eq!(
+            math.errors,
+            [
+                (11, "unsupported command `\\foo`".to_string()),
+                (25, "unbalanced `}`".to_string())
+            ]
+        );
+        assert!(body(&math).contains("<merror><mtext>\\foo</mtext></merror>"));
+
+        let math = Math::new(r"\begin{cases} 1 \end{matrix}", false);
+        assert_eq!(math.errors.len(), 1);
+        assert_eq!(
+            math.errors[0].1,
+            "`\\begin{cases}` closed by `\\end{matrix}`"
+        );
+    }
+}
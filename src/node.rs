@@ -1,4 +1,7 @@
-use crate::tokens::{CodeBlock, Heading, List, Paragraph, Quote};
+use crate::tokens::{
+    Admonition, CodeBlock, DefinitionList, Heading, HtmlBlock, List, MathBlock, Paragraph, Quote,
+    Table, TableOfContents, ThematicBreak,
+};
 
 /// Awrapper enum for all the supported MD blocks
 #[derive(Debug)]
@@ -6,18 +9,34 @@ pub enum Node {
     Heading(Heading),
     Paragraph(Paragraph),
     List(List),
+    DefinitionList(DefinitionList),
     CodeBlock(CodeBlock),
     Quote(Quote),
+    Table(Table),
+    Admonition(Admonition),
+    /// `[TOC]` marker, filled once the whole page is parsed
+    TableOfContents(TableOfContents),
+    ThematicBreak(ThematicBreak),
+    /// Raw HTML passed through verbatim
+    Html(HtmlBlock),
+    Math(MathBlock),
 }
 
 impl Node {
-    pub fn into_html(&self) -> String {
+    pub fn to_html(&self) -> String {
         match self {
-            Node::CodeBlock(code) => code.into_html(),
-            Node::Paragraph(p) => p.into_html(),
-            Node::List(l) => l.into_html(),
-            Node::Quote(q) => q.into_html(),
-            Node::Heading(h) => h.into_html(),
+            Node::CodeBlock(code) => code.to_html(),
+            Node::Paragraph(p) => p.to_html(),
+            Node::List(l) => l.to_html(),
+            Node::DefinitionList(d) => d.to_html(),
+            Node::Quote(q) => q.to_html(),
+            Node::Heading(h) => h.to_html(),
+            Node::Table(t) => t.to_html(),
+            Node::Admonition(a) => a.to_html(),
+            Node::TableOfContents(t) => t.to_html(),
+            Node::ThematicBreak(t) => t.to_html(),
+            Node::Html(h) => h.to_html(),
+            Node::Math(m) => m.to_html(),
         }
     }
 }
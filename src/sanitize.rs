@@ -0,0 +1,211 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+use crate::config::HtmlAllowList;
+
+/// Open or closing tag, or a comment
+pub const HTML_TAG: &str = r#"<[A-Za-z][A-Za-z0-9-]*(?:\s+[A-Za-z_:][A-Za-z0-9_.:-]*(?:\s*=\s*(?:[^\s"'=<>`]+|'[^']*'|"[^"]*"))?)*\s*/?>|</[A-Za-z][A-Za-z0-9-]*\s*>|<!--[\s\S]*?-->"#;
+
+/// Attributes holding a URL, they can't use a scheme other than http(s) and mailto
+const URL_ATTRIBUTES: [&str; 8] = [
+    "action",
+    "background",
+    "cite",
+    "formaction",
+    "href",
+    "poster",
+    "src",
+    "xlink:href",
+];
+
+/// Removes every tag and attribute missing from the allow list
+pub struct Sanitizer<'a> {
+    allow: &'a HtmlAllowList,
+}
+
+impl<'a> Sanitizer<'a> {
+    pub fn new(allow: &'a HtmlAllowList) -> Self {
+        Sanitizer { allow }
+    }
+
+    /// Keep the text and the allowed tags, the content of a removed `script` or `style` goes with it
+    pub fn clean(&self, html: &str) -> String {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(&format!(
+                r"{}|<\?[\s\S]*?\?>|<!\[CDATA\[[\s\S]*?\]\]>|<![A-Za-z][^>]*>",
+                HTML_TAG
+            ))
+            .unwrap()
+        });
+
+        let mut cleaned = String::with_capacity(html.len());
+        let mut skipped: Option<String> = None;
+        let mut last = 0;
+
+        for tag in RE.find_iter(html) {
+            if skipped.is_none() {
+                cleaned.push_str(&html[last..tag.start()].replace('<', "&lt;"));
+            }
+            last = tag.end();
+
+            let (name, closing) = tag_name(tag.as_str());
+            if let Some(skipped_name) = &skipped {
+                if closing && &name == skipped_name {
+                    skipped = None;
+                }
+                continue;
+            }
+
+            match self.clean_tag(tag.as_str()) {
+                Some(tag) => cleaned.push_str(&tag),
+                None if !closing && (name == "script" || name == "style") => skipped = Some(name),
+                None => {}
+            }
+        }
+
+        if skipped.is_none() {
+            cleaned.push_str(&html[last..].replace('<', "&lt;"));
+        }
+        cleaned
+    }
+
+    /// Allowed tag rewritten with its allowed attributes only
+    pub fn clean_tag(&self, tag: &str) -> Option<String> {
+        let (name, closing) = tag_name(tag);
+        if name.is_empty()
+            || !self
+                .allow
+                .tags
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(&name))
+        {
+            return None;
+        }
+        if closing {
+            return Some(format!("</{}>", name));
+        }
+
+        static RE_ATTR: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+            r#"(?P<name>[A-Za-z_:][A-Za-z0-9_.:-]*)(?:\s*=\s*(?:"(?P<double>[^"]*)"|'(?P<single>[^']*)'|(?P<bare>[^\s"'=<>`]+)))?"#,
+        )
+        .unwrap()
+        });
+
+        let attributes = &tag[name.len() + 1..tag.len() - 1];
+        let mut cleaned = format!("<{}", name);
+        for caps in RE_ATTR.captures_iter(attributes) {
+            let attr = &caps["name"];
+            let value = caps
+                .name("double")
+                .or(caps.name("single"))
+                .or(caps.name("bare"))
+                .map(|v| v.as_str());
+
+            if !self.is_allowed_attribute(attr, value) {
+                continue;
+            }
+            match value {
+                Some(value) => {
+                    let value = value
+                        .replace('"', "&quot;")
+                        .replace('<', "&lt;")
+                        .replace('>', "&gt;");
+                    cleaned.push_str(&format!(" {}=\"{}\"", attr, value));
+                }
+                None => cleaned.push_str(&format!(" {}", attr)),
+            }
+        }
+
+        if tag.ends_with("/>") {
+            cleaned.push_str(" /");
+        }
+        cleaned.push('>');
+        Some(cleaned)
+    }
+
+    fn is_allowed_attribute(&self, name: &str, value: Option<&str>) -> bool {
+        let name = name.to_lowercase();
+        if name.starts_with("on")
+            || !self
+                .allow
+                .attributes
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(&name))
+        {
+            return false;
+        }
+        !URL_ATTRIBUTES.contains(&name.as_str()) || value.is_some_and(is_safe_url)
+    }
+}
+
+/// Lowercase tag name, empty for comments and declarations, and whether it's a closing tag
+fn tag_name(tag: &str) -> (String, bool) {
+    let closing = tag.starts_with("</");
+    let name = tag
+        .trim_start_matches('<')
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>();
+    (name.to_lowercase(), closing)
+}
+
+/// Relative URLs or http(s) and mailto links, character references could hide another scheme
+pub fn is_safe_url(url: &str) -> bool {
+    let url = url.trim().to_lowercase();
+    if ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|s| url.starts_with(s))
+    {
+        return true;
+    }
+    if url.contains("&#") || url.contains("&colon") {
+        return false;
+    }
+    url.find([':', '/', '?', '#'])
+        .is_none_or(|i| !url[i..].starts_with(':'))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_keep_allowed_tags_and_attributes() {
+        let allow = HtmlAllowList::default();
+        let sanitizer = Sanitizer::new(&allow);
+
+        assert_eq!(
+            sanitizer.clean(r#"<details open class='x' onclick="steal()"><summary>More</summary>"#),
+            r#"<details open class="x"><summary>More</summary>"#
+        );
+        assert_eq!(
+            sanitizer.clean(r#"<img src="cat.png" alt="A &quot;cat&quot;" style="x"/>"#),
+            r#"<img src="cat.png" alt="A &quot;cat&quot;" />"#
+        );
+        assert_eq!(
+            sanitizer.clean("<td colspan=2>1 < 2</td>"),
+            r#"<td colspan="2">1 &lt; 2</td>"#
+        );
+    }
+
+    #[test]
+    fn it_remove_unsafe_html() {
+        let allow = HtmlAllowList::default();
+        let sanitizer = Sanitizer::new(&allow);
+
+        assert_eq!(sanitizer.clean("a<script>alert('<b>')</script>b"), "ab");
+        assert_eq!(sanitizer.clean("<form><input></form><!-- note -->"), "");
+        assert_eq!(
+            sanitizer.clean(
+                r#"<a href="javascript:alert(1)">x</a><a href="jav&#x61;script&#58;x">y</a>"#
+            ),
+            "<a>x</a><a>y</a>"
+        );
+        assert_eq!(
+            sanitizer.clean(r#"<a href="/guide/intro.html#setup">z</a>"#),
+            r#"<a href="/guide/intro.html#setup">z</a>"#
+        );
+    }
+}
@@ -1,146 +1,16 @@
-use regex::{Captures, Regex};
-
-#[derive(Debug)]
-pub enum InlineToken {
-    Link {
-        href: String,
-        text: String,
-        raw: String,
-    },
-    Bold {
-        value: String,
-        raw: String,
-    },
-    Code {
-        value: String,
-        raw: String,
-    },
-    Italic {
-        value: String,
-        raw: String,
-    },
-    Image {
-        alt: String,
-        src: String,
-        raw: String,
-    },
-}
-
-impl InlineToken {
-    fn extract(text: &String) -> Vec<InlineToken> {
-        // Match any one of these
-        let re_set = [
-            r"\[(?P<link_text>[^\]]+)\]\((?P<href>[^\]]+)\)", // Link
-            r"\*\*(?P<bold>[^\*]+)\*\*",                      // Bold text
-            r"_(?P<italic>[^_]+)_",                           // Italic text
-            r"`(?P<code>[^`]+)`",                             // Inline code
-            r"!\[(?P<alt>[^\]]+)\]\((?P<src>[^\]]+)\)",       // Image
-        ];
-
-        let re = Regex::new(&re_set.join("|")).unwrap();
-
-        let tokens = re
-            .captures_iter(text)
-            .map(|caps| {
-                let raw = caps[0].to_string();
-
-                let href = InlineToken::get_name(&caps, "href");
-                let link_text = InlineToken::get_name(&caps, "link_text");
-                let bold = InlineToken::get_name(&caps, "bold");
-                let italic = InlineToken::get_name(&caps, "italic");
-                let code = InlineToken::get_name(&caps, "code");
-                let img_src = InlineToken::get_name(&caps, "src");
-                let img_alt = InlineToken::get_name(&caps, "alt");
-
-                if href.is_some() && link_text.is_some() {
-                    InlineToken::Link {
-                        href: href.unwrap(),
-                        text: link_text.unwrap(),
-                        raw,
-                    }
-                } else if img_src.is_some() && img_alt.is_some() {
-                    InlineToken::Image {
-                        src: img_src.unwrap(),
-                        alt: img_alt.unwrap(),
-                        raw,
-                    }
-                } else if bold.is_some() {
-                    InlineToken::Bold {
-                        value: bold.unwrap(),
-                        raw,
-                    }
-                } else if italic.is_some() {
-                    InlineToken::Italic {
-                        value: italic.unwrap(),
-                        raw,
-                    }
-                } else if code.is_some() {
-                    InlineToken::Code {
-                        value: code.unwrap(),
-                        raw,
-                    }
-                } else {
-                    // Should never happen
-                    // Regex should never match other names
-                    panic!("Regex maching unsupported type")
-                }
-            })
-            .collect();
-
-        tokens
-    }
-
-    fn get_name(caps: &Captures, name: &str) -> Option<String> {
-        if caps.name(name).is_some() {
-            return Some(caps[name].to_string());
-        }
-
-        None
-    }
-
-    fn get_raw(&self) -> &String {
-        match &self {
-            InlineToken::Link { raw, .. } => raw,
-            InlineToken::Bold { raw, .. } => raw,
-            InlineToken::Code { raw, .. } => raw,
-            InlineToken::Italic { raw, .. } => raw,
-            InlineToken::Image { raw, .. } => raw,
-        }
-    }
-
-    fn mask_tokens(mut text: String, tokens: &Vec<InlineToken>) -> String {
-        tokens.iter().enumerate().for_each(|(idx, token)| {
-            text = text.replace(token.get_raw(), &format!("<${}>", idx + 1))
-        });
-
-        text
-    }
-
-    fn into_html(line: &str, tokens: &Vec<InlineToken>) -> String {
-        let mut new_line = line.to_string();
-        tokens.into_iter().enumerate().for_each(|(idx, t)| {
-            let html: String;
-            match t {
-                InlineToken::Bold { value, .. } => html = format!("<strong>{}</strong>", value),
-                InlineToken::Code { value, .. } => {
-                    html = format!("<span class='inline-code'>{}</span>", value)
-                }
-                InlineToken::Image { alt, src, .. } => {
-                    html = format!("<img src='{}' alt='{}'/>", src, alt);
-                }
-                InlineToken::Italic { value, .. } => {
-                    html = format!("<i>{}</i>", value);
-                }
-                InlineToken::Link { href, text, .. } => {
-                    html = format!("<a href='{}'>{}</a>", href, text)
-                }
-            }
-            new_line = new_line.replace(&format!("<${}>", idx + 1), &html);
-        });
-
-        new_line
-    }
-}
+use crate::{
+    config::{Link, RawHtml},
+    context::Context,
+    diagrams::SpecialLanguage,
+    documents::Document,
+    inline::InlineToken,
+    math::Math,
+    node::Node,
+    sanitize::{Sanitizer, HTML_TAG},
+    utils::{escape_html, indentation, slugify, strip_indentation},
+};
+use regex::Regex;
+use std::{ops::RangeInclusive, sync::LazyLock};
 
 #[derive(Debug)]
 pub enum HeadingType {
@@ -153,24 +23,24 @@ pub enum HeadingType {
 }
 
 impl HeadingType {
+    /// ATX heading marker, 1 to 6 `#` followed by a space or the end of the line
     pub fn new(line: &str) -> Option<HeadingType> {
-        let re = Regex::new(r"^(?P<type>#{1,})").unwrap();
-
-        if let Some(caps) = re.captures(line) {
-            let number_signs = &caps["type"];
-
-            let h_type = match number_signs.len() {
-                1 => HeadingType::H1,
-                2 => HeadingType::H2,
-                3 => HeadingType::H3,
-                4 => HeadingType::H4,
-                5 => HeadingType::H5,
-                _ => HeadingType::H6,
-            };
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^ {0,3}(?P<type>#{1,6})(?:[ \t]|$)").unwrap());
+        let caps = RE.captures(line)?;
+
+        Some(HeadingType::from_level(caps["type"].len()))
+    }
 
-            return Some(h_type);
+    fn from_level(level: usize) -> HeadingType {
+        match level {
+            1 => HeadingType::H1,
+            2 => HeadingType::H2,
+            3 => HeadingType::H3,
+            4 => HeadingType::H4,
+            5 => HeadingType::H5,
+            _ => HeadingType::H6,
         }
-        None
     }
 
     pub fn get_tag_num(&self) -> u8 {
@@ -188,321 +58,2386 @@ impl HeadingType {
 #[derive(Debug)]
 pub struct Heading {
     h_type: HeadingType,
-    text: String,
     inline_tokens: Vec<InlineToken>,
+    /// Unique in the page, from `{#custom-id}` or the heading text
+    id: String,
+    classes: Vec<String>,
 }
 
 impl Heading {
-    pub fn new(line: &str) -> Option<Heading> {
-        let line = line.trim();
-        let h_type = match HeadingType::new(line) {
-            Some(h) => h,
-            None => return None,
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> Option<Heading> {
+        let line = lines[idx];
+        let h_type = HeadingType::new(line)?;
+        let text = line.trim().trim_start_matches('#').trim();
+
+        // Optional closing sequence, it must follow a space: `## Title ##`
+        let without_closing = text.trim_end_matches('#');
+        let text = if without_closing.is_empty() {
+            ""
+        } else if without_closing.ends_with([' ', '\t']) {
+            without_closing.trim_end()
+        } else {
+            text
         };
 
-        // Extract text
-        let re = Regex::new(r"#{1,6}\s+(?P<text>.+)").unwrap();
+        Some(Heading::from_text(h_type, text, ctx.line_number(idx), ctx))
+    }
 
-        if let Some(caps) = re.captures(line) {
-            let mut text = (&caps["text"]).to_string();
-            let inline_tokens = InlineToken::extract(&mut text);
+    /// Paragraph lines underlined by `===` (level 1) or `---` (level 2)
+    pub fn setext(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^ {0,3}(?:(?P<h1>=+)|-+)[ \t]*$").unwrap());
+
+        let mut end_idx = idx + 1;
+        while end_idx < lines.len() {
+            if let Some(caps) = RE.captures(lines[end_idx]) {
+                let h_type = match caps.name("h1") {
+                    Some(_) => HeadingType::H1,
+                    None => HeadingType::H2,
+                };
+                let text = Paragraph::join_lines(&lines[idx..end_idx]);
+                let heading = Heading::from_text(h_type, &text, ctx.line_number(idx), ctx);
+
+                return (Some(heading), end_idx + 1);
+            }
 
-            return Some(Heading {
-                h_type,
-                text: InlineToken::mask_tokens(text, &inline_tokens),
-                inline_tokens,
-            });
+            if Paragraph::is_interrupted(lines, end_idx, ctx) {
+                break;
+            }
+            end_idx += 1;
         }
 
-        None
+        (None, idx)
+    }
+
+    /// `line` is the line of the page where the text starts
+    fn from_text(h_type: HeadingType, text: &str, line: usize, ctx: &Context) -> Heading {
+        // Extract the optional `{#id .class}` attributes
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^(?P<text>.*?)\s+\{(?P<attrs>\s*[#.][^{}]*)\}$").unwrap()
+        });
+        let attrs = RE
+            .captures(text)
+            .and_then(|caps| Some((caps.get(1)?.as_str(), Heading::attributes(&caps["attrs"])?)));
+
+        let (text, (custom_id, classes)) = match attrs {
+            Some((text, attrs)) => (text, attrs),
+            None => (text, (None, vec![])),
+        };
+        let inline_tokens = InlineToken::parse(text, line, ctx);
+
+        let id = match custom_id {
+            Some(id) => {
+                if !ctx.claim_id(&id) {
+                    ctx.warn(line, &format!("duplicate heading id `{}`", id));
+                }
+                id
+            }
+            None => ctx.unique_id(&slugify(&InlineToken::plain_text(&inline_tokens))),
+        };
+
+        Heading {
+            h_type,
+            inline_tokens,
+            id,
+            classes,
+        }
     }
 
-    pub fn into_html(&self) -> String {
+    pub fn level(&self) -> u8 {
+        self.h_type.get_tag_num()
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Heading text without markup
+    pub fn text(&self) -> String {
+        InlineToken::plain_text(&self.inline_tokens)
+    }
+
+    /// `#id .class .other`, `None` if any part isn't an id or a class
+    fn attributes(attrs: &str) -> Option<(Option<String>, Vec<String>)> {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^(?P<kind>[#.])(?P<name>[\w-]+)$").unwrap());
+        let mut id = None;
+        let mut classes = vec![];
+        for attr in attrs.split_whitespace() {
+            let caps = RE.captures(attr)?;
+            match &caps["kind"] {
+                "#" => id = Some(caps["name"].to_string()),
+                _ => classes.push(caps["name"].to_string()),
+            }
+        }
+
+        Some((id, classes))
+    }
+
+    pub fn to_html(&self) -> String {
         let tag = self.h_type.get_tag_num();
-        let text = InlineToken::into_html(&self.text, &self.inline_tokens);
-        format!("<h{}>{}</h{}>", tag, text, tag)
+        let text = InlineToken::render(&self.inline_tokens);
+        let id = escape_html(&self.id);
+        let class = if self.classes.is_empty() {
+            String::new()
+        } else {
+            format!(" class='{}'", self.classes.join(" "))
+        };
+
+        format!(
+            "<h{} id='{}'{}>{}<a class='heading-anchor' href='#{}' aria-label='Permalink'>#</a></h{}>",
+            tag, id, class, text, id, tag
+        )
     }
 }
 
+/// `***`, `---` or `___`, spaces between the characters are allowed
 #[derive(Debug)]
-pub struct Paragraph {
-    text: String,
-    inline_tokens: Vec<InlineToken>,
-}
-/// Should match any text without in special tokens (ex: # or *)
-impl Paragraph {
-    pub fn new(line: &str) -> Option<Self> {
-        let mut text = line.trim().to_string();
-        if text.len() == 0 {
-            return None;
-        }
-
-        let inline_tokens = InlineToken::extract(&mut text);
-        let text = InlineToken::mask_tokens(text, &inline_tokens);
+pub struct ThematicBreak;
 
-        Some(Paragraph {
-            text,
-            inline_tokens,
-        })
+impl ThematicBreak {
+    pub fn new(line: &str) -> Option<ThematicBreak> {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^ {0,3}(?:(?:\*[ \t]*){3,}|(?:-[ \t]*){3,}|(?:_[ \t]*){3,})$").unwrap()
+        });
+        RE.is_match(line).then_some(ThematicBreak)
     }
 
-    pub fn into_html(&self) -> String {
-        let text = InlineToken::into_html(&self.text, &self.inline_tokens);
-        format!("<p>{}</p>", text,)
+    pub fn to_html(&self) -> String {
+        "<hr/>".to_string()
     }
 }
 
-/// Support for <ol> </ol> or <ul> </ul>
-#[derive(Debug, PartialEq, Eq)]
-pub enum ListType {
-    Ordered,
-    Unordered,
-}
+/// Tags starting an HTML block that ends at the next blank line
+const HTML_BLOCK_TAGS: &str = "address|article|aside|base|basefont|blockquote|body|caption|center|col|colgroup|dd|details|dialog|dir|div|dl|dt|fieldset|figcaption|figure|footer|form|frame|frameset|h[1-6]|head|header|hr|html|iframe|legend|li|link|main|menu|menuitem|nav|noframes|ol|optgroup|option|p|param|search|section|source|summary|table|tbody|td|tfoot|th|thead|title|tr|track|ul";
 
-/// Internal representation of <li> tag
+/// Raw HTML lines written to the page as is, or sanitized, following the CommonMark start and end conditions
 #[derive(Debug)]
-pub struct ListItem {
-    text: String,
-    inline_tokens: Vec<InlineToken>,
+pub struct HtmlBlock {
+    html: String,
 }
 
-impl ListItem {
-    /// Create new list item
-    fn new(line: &str) -> Self {
-        let text = line.trim().to_string();
-        let inline_tokens = InlineToken::extract(&text);
-        let text = InlineToken::mask_tokens(text, &inline_tokens);
-        let text = ListItem::trim(&text);
-        Self {
-            text,
-            inline_tokens,
+impl HtmlBlock {
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        if ctx.config.raw_html == RawHtml::Escape {
+            return (None, idx);
         }
-    }
 
-    /// Remove list markers like `* | - | 1. | 2.`
-    fn trim(line: &str) -> String {
-        let list_type = List::get_list_type(line).unwrap();
-        match list_type {
-            ListType::Unordered => line.replace("-", "").trim().into(),
-            ListType::Ordered => {
-                // Match every line that starts with a number 1. | 2. | ....
-                let re = Regex::new(r"^(?P<idx>[0-9]+)\.").unwrap();
-                let caps = re.captures(line).unwrap();
-                let raw_token = &caps[0];
-                line.replace(raw_token, "").trim().into()
+        let end_marker = match HtmlBlock::start(lines[idx]) {
+            Some(end_marker) => end_marker,
+            None => {
+                // A lone complete tag can start a block but not interrupt a paragraph
+                static RE: LazyLock<Regex> = LazyLock::new(|| {
+                    Regex::new(&format!(r"^ {{0,3}}(?:{})[ \t]*$", HTML_TAG)).unwrap()
+                });
+                if !RE.is_match(lines[idx]) {
+                    return (None, idx);
+                }
+                None
+            }
+        };
+
+        let mut end_idx = idx;
+        match end_marker {
+            Some(marker) => {
+                while end_idx < lines.len() && !lines[end_idx].to_lowercase().contains(marker) {
+                    end_idx += 1;
+                }
+                end_idx = (end_idx + 1).min(lines.len());
+            }
+            None => {
+                while end_idx < lines.len() && !lines[end_idx].trim().is_empty() {
+                    end_idx += 1;
+                }
             }
         }
+
+        let html = lines[idx..end_idx].join("\n");
+        let html = match ctx.config.raw_html {
+            RawHtml::Sanitize => Sanitizer::new(&ctx.config.html_allow_list).clean(&html),
+            _ => html,
+        };
+        (Some(HtmlBlock { html }), end_idx)
+    }
+
+    /// Check if the line starts one of the blocks that can interrupt a paragraph, with their end marker if any
+    pub fn start(line: &str) -> Option<Option<&'static str>> {
+        let text = match line.len() - line.trim_start_matches(' ').len() {
+            0..=3 => line.trim_start_matches(' '),
+            _ => return None,
+        };
+
+        static RE_RAW_TEXT: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^<(?i)(?P<tag>script|pre|style|textarea)(?:[ \t>]|$)").unwrap()
+        });
+        if let Some(caps) = RE_RAW_TEXT.captures(text) {
+            let marker = match &caps["tag"].to_lowercase()[..] {
+                "script" => "</script>",
+                "pre" => "</pre>",
+                "style" => "</style>",
+                _ => "</textarea>",
+            };
+            return Some(Some(marker));
+        }
+
+        if text.starts_with("<!--") {
+            Some(Some("-->"))
+        } else if text.starts_with("<?") {
+            Some(Some("?>"))
+        } else if text.starts_with("<![CDATA[") {
+            Some(Some("]]>"))
+        } else if text.starts_with("<!") && text[2..].starts_with(|c: char| c.is_ascii_alphabetic())
+        {
+            Some(Some(">"))
+        } else {
+            static RE: LazyLock<Regex> = LazyLock::new(|| {
+                Regex::new(&format!(r"^</?(?i:{})(?:[ \t>]|/>|$)", HTML_BLOCK_TAGS)).unwrap()
+            });
+            RE.is_match(text).then_some(None)
+        }
     }
 
-    pub fn into_html(&self) -> String {
-        let text = InlineToken::into_html(&self.text, &self.inline_tokens);
-        format!("<li>{text}</li>")
+    pub fn to_html(&self) -> String {
+        self.html.clone()
     }
 }
 
+/// `$$` display math on its own lines, converted to MathML
 #[derive(Debug)]
-/// Collection of list items
-pub struct List {
-    list_type: ListType,
-    items: Vec<ListItem>,
+pub struct MathBlock {
+    mathml: String,
 }
 
-impl List {
-    pub fn new(lines: &Vec<&str>, mut idx: usize) -> (Option<Self>, usize) {
-        let mut items = vec![];
-        let mut list_type = ListType::Ordered;
+impl MathBlock {
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        if !ctx.config.extensions.math || !MathBlock::is_start(lines[idx]) {
+            return (None, idx);
+        }
 
-        loop {
-            if idx >= lines.len() {
-                break;
+        // `$$ x $$` on a single line or `$$` ... `$$` over several
+        let first = &lines[idx].trim()[2..];
+        let (tex, end_idx) = match first.strip_suffix("$$") {
+            Some(tex) => (tex.to_string(), idx + 1),
+            // Text after the closing `$$` makes it inline math in a paragraph
+            None if first.contains("$$") => return (None, idx),
+            None => {
+                let Some(offset) = lines[idx + 1..]
+                    .iter()
+                    .position(|l| l.trim_end().ends_with("$$"))
+                else {
+                    return (None, idx);
+                };
+                let last = lines[idx + 1 + offset].trim_end();
+                let mut tex_lines = vec![first];
+                tex_lines.extend(&lines[idx + 1..idx + 1 + offset]);
+                tex_lines.push(&last[..last.len() - 2]);
+                (tex_lines.join("\n"), idx + offset + 2)
             }
+        };
 
-            let line = lines[idx];
-
-            if line.trim().len() == 0 {
-                idx += 1;
-                continue;
-            }
+        let math = Math::new(&tex, true);
+        for (offset, message) in &math.errors {
+            let line = tex[..*offset].matches('\n').count();
+            ctx.warn(ctx.line_number(idx + line), message);
+        }
+        (
+            Some(MathBlock {
+                mathml: math.mathml,
+            }),
+            end_idx,
+        )
+    }
 
-            list_type = match List::get_list_type(&line) {
-                Some(t) => t,
-                None => break,
-            };
+    pub fn is_start(line: &str) -> bool {
+        indentation(line) < 4 && line.trim_start().starts_with("$$")
+    }
 
-            let item = ListItem::new(&line);
-            items.push(item);
+    pub fn to_html(&self) -> String {
+        self.mathml.clone()
+    }
+}
 
-            idx += 1;
+#[derive(Debug)]
+pub struct Paragraph {
+    inline_tokens: Vec<InlineToken>,
+}
+/// Should match any text without in special tokens (ex: # or *)
+impl Paragraph {
+    /// Merge consecutive lines until a blank line or the start of another block
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        if lines[idx].trim().is_empty() {
+            return (None, idx);
         }
-        if items.len() != 0 {
-            return (
-                Some(Self {
-                    list_type,
-                    items: items,
-                }),
-                idx,
-            );
+
+        let mut end_idx = idx + 1;
+        while end_idx < lines.len() && !Paragraph::is_interrupted(lines, end_idx, ctx) {
+            end_idx += 1;
         }
 
-        (None, idx)
+        let paragraph = Paragraph::from_lines(&lines[idx..end_idx], ctx.line_number(idx), ctx);
+        (Some(paragraph), end_idx)
     }
 
-    fn get_list_type(line: &str) -> Option<ListType> {
-        let line = line.trim();
-        // Match every line that starts with a number 1. | 2. | ....
-        let re = Regex::new(r"^(?P<idx>[0-9]+)\.").unwrap();
+    /// `line` is the line of the page where the paragraph starts
+    fn from_lines(lines: &[&str], line: usize, ctx: &Context) -> Self {
+        let inline_tokens = InlineToken::parse(&Paragraph::join_lines(lines), line, ctx);
 
-        match line {
-            line if line.starts_with("- ") => Some(ListType::Unordered),
-            line if line.starts_with("* ") => Some(ListType::Unordered),
-            line if re.captures(line).is_some() => Some(ListType::Ordered),
-            _ => None,
-        }
+        Paragraph { inline_tokens }
     }
 
-    pub fn into_html(&self) -> String {
-        let list_items = self
-            .items
+    /// Join paragraph lines, keeping the trailing spaces or backslash that mark a hard line break
+    fn join_lines(lines: &[&str]) -> String {
+        lines
             .iter()
-            .map(|list_item| list_item.into_html())
-            .collect::<Vec<String>>()
-            .join("\n");
+            .map(|l| l.trim_start())
+            .collect::<Vec<&str>>()
+            .join("\n")
+            .trim_end()
+            .to_string()
+    }
 
-        let mut tag = "ol";
-        if self.list_type == ListType::Unordered {
-            tag = "ul"
+    /// Check if the line at `idx` ends a paragraph instead of continuing it
+    pub fn is_interrupted(lines: &[&str], idx: usize, ctx: &Context) -> bool {
+        let line = lines[idx];
+        let text = line.trim_start();
+
+        if text.is_empty() || HeadingType::new(line).is_some() || text.starts_with('>') {
+            return true;
         }
 
-        format!("<{}>{}</{}>", tag, list_items, tag)
-    }
-}
+        if ThematicBreak::new(line).is_some() {
+            return true;
+        }
 
-#[derive(Debug)]
-pub struct CodeBlock {
-    lang: String,
-    lines: Vec<String>,
-}
+        if CodeFence::new(line).is_some() || Admonition::is_fence(line) {
+            return true;
+        }
 
-impl CodeBlock {
-    pub fn new(lines: &Vec<&str>, idx: usize) -> (Option<Self>, usize) {
-        let line = lines[idx];
-        // Regex to match the first line in a code block
-        let re = Regex::new(r"`{3}(?P<lang>[^\n]+)").unwrap();
+        // Escaped HTML and disabled math are paragraph text
+        if ctx.config.raw_html != RawHtml::Escape && HtmlBlock::start(line).is_some() {
+            return true;
+        }
 
-        let caps = match re.captures(line) {
-            Some(c) => c,
-            None => return (None, idx),
-        };
+        if ctx.config.extensions.math && MathBlock::is_start(line) {
+            return true;
+        }
 
-        let mut end_idx = idx;
+        if Table::is_start(lines, idx) {
+            return true;
+        }
 
-        loop {
-            end_idx += 1;
-            if end_idx >= lines.len() {
-                break;
-            }
+        // Only non empty bullets or ordered lists starting at 1 can interrupt a paragraph
+        match ListMarker::new(line) {
+            Some(m) if m.content.trim().is_empty() => false,
+            Some(m) => m.list_type == ListType::Unordered || m.start == 1,
+            None => false,
+        }
+    }
 
-            if lines[end_idx].trim() == "```" {
-                break;
+    /// Unindented text right after a paragraph nested in a list item or quote still belongs to it
+    pub fn is_lazy_continuation(
+        content: &[&str],
+        lines: &[&str],
+        idx: usize,
+        ctx: &Context,
+    ) -> bool {
+        let last = content
+            .last()
+            .map_or("", |l| Paragraph::strip_container_markers(l));
+        let is_indented_code = content.last().is_some_and(|l| indentation(l) >= 4)
+            && (content.len() < 2 || content[content.len() - 2].trim().is_empty());
+        let mut fence: Option<CodeFence> = None;
+        for line in content {
+            match &fence {
+                Some(open) if open.is_closed_by(line) => fence = None,
+                Some(_) => {}
+                None => fence = CodeFence::new(line),
             }
         }
 
-        let code_lines = (&lines[idx + 1..end_idx])
-            .into_iter()
-            .map(|l| l.to_string())
-            .collect();
+        // The innermost block of the last line must be a paragraph, not a heading, fence...
+        let is_paragraph = !Paragraph::is_interrupted(&[last], 0, ctx) && !is_indented_code;
 
-        (
-            Some(CodeBlock {
-                lang: caps["lang"].to_string(),
-                lines: code_lines,
-            }),
-            end_idx,
-        )
+        // Any list marker starts a new item, even those that can't interrupt a paragraph
+        is_paragraph
+            && fence.is_none()
+            && ListMarker::new(lines[idx]).is_none()
+            && !Paragraph::is_interrupted(lines, idx, ctx)
     }
 
-    pub fn into_html(&self) -> String {
-        let code = self.lines.join("<br/>");
-        // See https://highlightjs.org/
-        format!(
-            "<pre><code class='language-{}'>{}</code></pre>",
-            self.lang, code
-        )
+    /// Content of a line once the quote and list markers opening nested blocks are removed
+    fn strip_container_markers(mut line: &str) -> &str {
+        loop {
+            let text = line.trim_start();
+            if indentation(line) < 4 && text.starts_with('>') {
+                line = text[1..].strip_prefix(' ').unwrap_or(&text[1..]);
+            } else if let Some(marker) = ListMarker::new(line) {
+                line = marker.content;
+            } else {
+                return line;
+            }
+        }
+    }
+
+    /// Paragraph content without the wrapping <p> tag
+    pub fn inline_html(&self) -> String {
+        InlineToken::render(&self.inline_tokens)
     }
+
+    pub fn to_html(&self) -> String {
+        format!("<p>{}</p>", self.inline_html())
+    }
+}
+
+/// Support for <ol> </ol> or <ul> </ul>
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ListType {
+    Ordered,
+    Unordered,
 }
 
+/// List marker at the start of a line like `* | - | + | 1. | 2)`
 #[derive(Debug)]
-pub struct QuoteLine {
-    text: String,
-    inline_tokens: Vec<InlineToken>,
+struct ListMarker<'a> {
+    list_type: ListType,
+    /// Bullet char or the delimiter after the number (`.` or `)`)
+    delimiter: char,
+    start: u32,
+    /// Column where the item content starts, nested blocks must be indented up to it
+    content_indent: usize,
+    content: &'a str,
 }
 
-impl QuoteLine {
-    fn new(line: &str) -> Self {
-        let line = line.trim().to_string();
-        let inline_tokens = InlineToken::extract(&line);
-        let text = InlineToken::mask_tokens(line, &inline_tokens);
-        QuoteLine {
-            text,
-            inline_tokens,
+impl<'a> ListMarker<'a> {
+    fn new(line: &'a str) -> Option<Self> {
+        // `* * *` or `- - -` are thematic breaks, not list items
+        let indent = indentation(line);
+        if indent > 3 || ThematicBreak::new(line).is_some() {
+            return None;
         }
-    }
 
-    pub fn into_html(&self) -> String {
-        let quote = InlineToken::into_html(&self.text, &self.inline_tokens);
+        // Match every line that starts with a bullet or a number 1. | 2) | ....
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^(?:(?P<bullet>[-*+])|(?P<idx>[0-9]{1,9})(?P<delimiter>[.)]))(?:[ \t]+|$)")
+                .unwrap()
+        });
+        let text = line.trim_start();
+        let caps = RE.captures(text)?;
+        let marker = &caps[0];
+
+        let (list_type, delimiter, start) = match caps.name("bullet") {
+            Some(bullet) => (ListType::Unordered, bullet.as_str().chars().next()?, 1),
+            None => (
+                ListType::Ordered,
+                caps["delimiter"].chars().next()?,
+                caps["idx"].parse().ok()?,
+            ),
+        };
+
+        let content = &text[marker.len()..];
+        let marker_width = marker.trim_end().len();
+        let spaces = marker.len() - marker_width;
+        // Content indented by 5+ spaces is an indented code block, only one space belongs to the marker
+        let padding = match spaces {
+            0 => 1,
+            n if n > 4 || content.is_empty() => 1,
+            n => n,
+        };
 
-        format!("<p>{}<p>", quote)
+        Some(ListMarker {
+            list_type,
+            delimiter,
+            start,
+            content_indent: indent + marker_width + padding,
+            content,
+        })
     }
 }
 
+/// Internal representation of <li> tag, owns every block nested under it
 #[derive(Debug)]
-pub struct Quote {
-    lines: Vec<QuoteLine>,
+pub struct ListItem {
+    /// Task list state for `- [ ]` or `- [x]` items
+    checked: Option<bool>,
+    children: Vec<Node>,
 }
 
-impl Quote {
-    pub fn new(lines: &Vec<&str>, idx: usize) -> (Option<Self>, usize) {
-        let line = lines[idx].trim();
+impl ListItem {
+    /// Create new list item from its lines with the marker and indentation already removed,
+    /// `first_line` is the line of the page with the marker
+    fn new(lines: &[&str], first_line: usize, ctx: &Context) -> Self {
+        let mut lines = lines.to_vec();
+        let checked = ListItem::task_marker(lines[0]).map(|(checked, text)| {
+            lines[0] = text;
+            checked
+        });
 
-        if !line.starts_with(">") {
-            return (None, idx);
+        Self {
+            checked,
+            children: Document::parse_nodes(&lines, first_line, ctx),
         }
+    }
 
-        let mut end_idx = idx;
-        loop {
-            end_idx += 1;
-            if end_idx >= lines.len() || !lines[end_idx].starts_with(">") {
+    /// Lines of a list item or a definition, `first` is the text after its marker: lines indented
+    /// past `content_indent`, blank lines followed by more of them and lazy continuations, unless
+    /// `is_sibling` tells the line starts the next item
+    fn collect_container_lines<'a>(
+        lines: &[&'a str],
+        idx: usize,
+        first: &'a str,
+        content_indent: usize,
+        ctx: &Context,
+        is_sibling: impl Fn(usize) -> bool,
+    ) -> (Vec<&'a str>, usize) {
+        let mut item_lines = vec![first];
+        let mut end_idx = idx + 1;
+        while end_idx < lines.len() {
+            let line = lines[end_idx];
+            if line.trim().is_empty() {
+                // Blank lines belong to the item only if it continues after them
+                let next = lines[end_idx..].iter().find(|l| !l.trim().is_empty());
+                match next {
+                    Some(next) if indentation(next) >= content_indent => item_lines.push(""),
+                    _ => break,
+                }
+            } else if indentation(line) >= content_indent {
+                item_lines.push(strip_indentation(line, content_indent));
+            } else if !is_sibling(end_idx)
+                && Paragraph::is_lazy_continuation(&item_lines, lines, end_idx, ctx)
+            {
+                item_lines.push(line.trim_start());
+            } else {
                 break;
             }
+            end_idx += 1;
         }
+        (item_lines, end_idx)
+    }
 
-        let quote_lines = lines[idx..end_idx]
-            .into_iter()
-            .map(|&l| QuoteLine::new(l))
-            .collect();
-        (Some(Quote { lines: quote_lines }), end_idx)
+    /// Blocks of a list item or a definition
+    fn render_children(children: &[Node], tight: bool) -> impl Iterator<Item = String> + '_ {
+        children.iter().map(move |node| match node {
+            // Paragraphs inside tight lists are rendered without <p> tags
+            Node::Paragraph(p) if tight => p.inline_html(),
+            node => node.to_html(),
+        })
     }
 
-    pub fn into_html(&self) -> String {
-        let quote = self
-            .lines
-            .iter()
-            .map(|l| l.into_html())
+    /// Match task markers like `[ ]`, `[x]` or `[X]` and return the text after it
+    fn task_marker(line: &str) -> Option<(bool, &str)> {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^\[(?P<mark>[ xX])\](?:[ \t]+|$)").unwrap());
+        let caps = RE.captures(line)?;
+        let checked = &caps["mark"] != " ";
+
+        Some((checked, &line[caps[0].len()..]))
+    }
+
+    pub fn to_html(&self, tight: bool) -> String {
+        let mut children = ListItem::render_children(&self.children, tight);
+
+        let checked = match self.checked {
+            Some(checked) => checked,
+            None => return format!("<li>{}</li>", children.collect::<Vec<String>>().join("\n")),
+        };
+
+        let checkbox = format!(
+            "<input type='checkbox' class='task-list-item-checkbox' disabled{}/>",
+            if checked { " checked" } else { "" }
+        );
+
+        // Keep the checkbox on the same line as the item text
+        let text = match self.children.first() {
+            Some(Node::Paragraph(p)) if !tight => {
+                children.next();
+                format!("<p>{}{}</p>", checkbox, p.inline_html())
+            }
+            _ => format!("{}{}", checkbox, children.next().unwrap_or_default()),
+        };
+        let text = std::iter::once(text)
+            .chain(children)
             .collect::<Vec<String>>()
-            .join("\r");
+            .join("\n");
 
-        format!("<quote>{}<quote>", quote)
+        format!("<li class='task-list-item'>{text}</li>")
     }
 }
 
 #[derive(Debug)]
-pub struct LineBreak;
+/// Collection of list items
+pub struct List {
+    list_type: ListType,
+    start: u32,
+    /// Loose lists have blank lines between their items
+    tight: bool,
+    items: Vec<ListItem>,
+}
+
+impl List {
+    pub fn new(lines: &[&str], mut idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        let first = match ListMarker::new(lines[idx]) {
+            Some(marker) => marker,
+            None => return (None, idx),
+        };
+
+        let mut items = vec![];
+        let mut tight = true;
+
+        while idx < lines.len() {
+            let marker = match ListMarker::new(lines[idx]) {
+                Some(m) if m.list_type == first.list_type && m.delimiter == first.delimiter => m,
+                _ => break,
+            };
+
+            let (item_lines, end_idx) = ListItem::collect_container_lines(
+                lines,
+                idx,
+                marker.content,
+                marker.content_indent,
+                ctx,
+                |_| false,
+            );
+
+            // A blank line between two blocks of the same item makes the list loose
+            let has_gap = item_lines.iter().skip(1).any(|l| l.is_empty());
+            items.push(ListItem::new(&item_lines, ctx.line_number(idx), ctx));
+
+            // Skip blank lines separating this item from the next one
+            let mut next_idx = end_idx;
+            while next_idx < lines.len() && lines[next_idx].trim().is_empty() {
+                next_idx += 1;
+            }
+
+            let has_sibling = next_idx < lines.len()
+                && ListMarker::new(lines[next_idx]).is_some_and(|m| {
+                    m.list_type == first.list_type && m.delimiter == first.delimiter
+                });
+
+            if has_gap || (has_sibling && next_idx > end_idx) {
+                tight = false;
+            }
+
+            idx = if has_sibling { next_idx } else { end_idx };
+            if !has_sibling {
+                break;
+            }
+        }
+
+        (
+            Some(Self {
+                list_type: first.list_type,
+                start: first.start,
+                tight,
+                items,
+            }),
+            idx,
+        )
+    }
+
+    pub fn to_html(&self) -> String {
+        let list_items = self
+            .items
+            .iter()
+            .map(|list_item| list_item.to_html(self.tight))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        match self.list_type {
+            ListType::Ordered if self.start != 1 => {
+                format!("<ol start='{}'>{}</ol>", self.start, list_items)
+            }
+            ListType::Ordered => format!("<ol>{}</ol>", list_items),
+            ListType::Unordered => format!("<ul>{}</ul>", list_items),
+        }
+    }
+}
+
+/// Term lines followed by one or more `: definition` lines
+#[derive(Debug)]
+pub struct DefinitionList {
+    /// Loose lists have blank lines between a term and its definition or inside a definition
+    tight: bool,
+    items: Vec<DefinitionItem>,
+}
+
+#[derive(Debug)]
+struct DefinitionItem {
+    terms: Vec<Vec<InlineToken>>,
+    definitions: Vec<Vec<Node>>,
+}
+
+impl DefinitionList {
+    pub fn new(lines: &[&str], mut idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        if !ctx.config.extensions.definition_lists {
+            return (None, idx);
+        }
+
+        let mut items = vec![];
+        let mut tight = true;
+        while let Some((terms_end, mut def_idx)) = DefinitionList::terms(lines, idx, ctx) {
+            if def_idx > terms_end {
+                tight = false;
+            }
+
+            let terms = (idx..terms_end)
+                .map(|i| InlineToken::parse(lines[i].trim(), ctx.line_number(i), ctx))
+                .collect();
+
+            let mut definitions = vec![];
+            while let Some((content, content_indent)) = DefinitionList::marker(lines[def_idx]) {
+                // Another definition or term ends the definition instead of continuing it
+                let (def_lines, end_idx) = ListItem::collect_container_lines(
+                    lines,
+                    def_idx,
+                    content,
+                    content_indent,
+                    ctx,
+                    |i| {
+                        DefinitionList::marker(lines[i]).is_some()
+                            || DefinitionList::terms(lines, i, ctx).is_some()
+                    },
+                );
+
+                if def_lines.iter().skip(1).any(|l| l.is_empty()) {
+                    tight = false;
+                }
+                let first_line = ctx.line_number(def_idx);
+                definitions.push(Document::parse_nodes(&def_lines, first_line, ctx));
+
+                // Other definitions of the same term can follow after blank lines
+                let next_idx = DefinitionList::skip_blank_lines(lines, end_idx);
+                def_idx = next_idx.min(lines.len() - 1);
+                idx = end_idx;
+                if next_idx == lines.len() || DefinitionList::marker(lines[next_idx]).is_none() {
+                    break;
+                }
+            }
+            items.push(DefinitionItem { terms, definitions });
+
+            // The list goes on if another term follows
+            let next_idx = DefinitionList::skip_blank_lines(lines, idx);
+            if DefinitionList::terms(lines, next_idx, ctx).is_none() {
+                break;
+            }
+            idx = next_idx;
+        }
+
+        if items.is_empty() {
+            return (None, idx);
+        }
+        (Some(DefinitionList { tight, items }), idx)
+    }
+
+    /// End of the term lines starting at `idx` and start of their first definition, which can
+    /// come after a blank line
+    fn terms(lines: &[&str], idx: usize, ctx: &Context) -> Option<(usize, usize)> {
+        let mut end_idx = idx;
+        while end_idx < lines.len()
+            && !lines[end_idx].trim().is_empty()
+            && DefinitionList::marker(lines[end_idx]).is_none()
+        {
+            // A term can't be another block, nor the underline of a setext heading
+            let line = lines[end_idx];
+            let is_underline = line.trim().chars().all(|c| c == '=');
+            if end_idx > idx && (Paragraph::is_interrupted(lines, end_idx, ctx) || is_underline) {
+                return None;
+            }
+            end_idx += 1;
+        }
+        if end_idx == idx {
+            return None;
+        }
+
+        let def_idx = match lines.get(end_idx) {
+            Some(line) if line.trim().is_empty() => end_idx + 1,
+            _ => end_idx,
+        };
+        let has_definition = lines
+            .get(def_idx)
+            .is_some_and(|l| DefinitionList::marker(l).is_some());
+        has_definition.then_some((end_idx, def_idx))
+    }
+
+    /// `: text`, with the text and the indentation of the lines that continue it
+    fn marker(line: &str) -> Option<(&str, usize)> {
+        static RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^ {0,3}:(?:[ \t]+|$)").unwrap());
+        let marker = RE.find(line)?;
+        Some((&line[marker.end()..], marker.end().max(2)))
+    }
+
+    fn skip_blank_lines(lines: &[&str], mut idx: usize) -> usize {
+        while idx < lines.len() && lines[idx].trim().is_empty() {
+            idx += 1;
+        }
+        idx
+    }
+
+    pub fn to_html(&self) -> String {
+        let items = self
+            .items
+            .iter()
+            .map(|item| {
+                let terms = item
+                    .terms
+                    .iter()
+                    .map(|term| format!("<dt>{}</dt>", InlineToken::render(term)));
+                let definitions = item.definitions.iter().map(|children| {
+                    let children = ListItem::render_children(children, self.tight)
+                        .collect::<Vec<String>>()
+                        .join("\n");
+                    format!("<dd>{}</dd>", children)
+                });
+                terms.chain(definitions).collect::<Vec<String>>().join("\n")
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        format!("<dl>{}</dl>", items)
+    }
+}
+
+/// Column alignment taken from the table delimiter row
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+impl Alignment {
+    /// Parse a single delimiter cell like `---`, `:--`, `:-:` or `--:`
+    fn new(cell: &str) -> Option<Alignment> {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^(?P<left>:)?-+(?P<right>:)?$").unwrap());
+        let caps = RE.captures(cell.trim())?;
+
+        let alignment = match (caps.name("left"), caps.name("right")) {
+            (Some(_), Some(_)) => Alignment::Center,
+            (Some(_), None) => Alignment::Left,
+            (None, Some(_)) => Alignment::Right,
+            (None, None) => Alignment::None,
+        };
+
+        Some(alignment)
+    }
+
+    fn style(&self) -> &str {
+        match self {
+            Alignment::None => "",
+            Alignment::Left => " style='text-align: left'",
+            Alignment::Center => " style='text-align: center'",
+            Alignment::Right => " style='text-align: right'",
+        }
+    }
+}
+
+/// Internal representation of <th> or <td> tag
+#[derive(Debug)]
+pub struct TableCell {
+    inline_tokens: Vec<InlineToken>,
+}
+
+impl TableCell {
+    fn new(cell: &str, line: usize, ctx: &Context) -> Self {
+        TableCell {
+            inline_tokens: InlineToken::parse(cell.trim(), line, ctx),
+        }
+    }
+
+    fn to_html(&self, tag: &str, alignment: Alignment) -> String {
+        let text = InlineToken::render(&self.inline_tokens);
+        format!("<{}{}>{}</{}>", tag, alignment.style(), text, tag)
+    }
+}
+
+/// GFM pipe table: a header row, a delimiter row and any number of body rows
+#[derive(Debug)]
+pub struct Table {
+    alignments: Vec<Alignment>,
+    head: Vec<TableCell>,
+    rows: Vec<Vec<TableCell>>,
+}
+
+impl Table {
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        if !Table::is_start(lines, idx) {
+            return (None, idx);
+        }
+
+        let head = Table::split_row(lines[idx]);
+        let alignments = Table::get_alignments(lines[idx + 1]).unwrap();
+        let head = head
+            .iter()
+            .map(|cell| TableCell::new(cell, ctx.line_number(idx), ctx))
+            .collect();
+
+        let mut rows = vec![];
+        let mut end_idx = idx + 2;
+        while end_idx < lines.len() {
+            let line = lines[end_idx];
+            if line.trim().is_empty() || !line.contains('|') {
+                break;
+            }
+
+            let mut cells = Table::split_row(line);
+            // Body rows are padded or truncated to match the header
+            cells.resize(alignments.len(), String::new());
+            let line = ctx.line_number(end_idx);
+            let row = cells.iter().map(|cell| TableCell::new(cell, line, ctx));
+            rows.push(row.collect());
+            end_idx += 1;
+        }
+
+        (
+            Some(Table {
+                alignments,
+                head,
+                rows,
+            }),
+            end_idx,
+        )
+    }
+
+    /// A header row followed by a delimiter row with the same number of cells
+    fn is_start(lines: &[&str], idx: usize) -> bool {
+        if idx + 1 >= lines.len() || !lines[idx].contains('|') {
+            return false;
+        }
+
+        let head = Table::split_row(lines[idx]);
+        Table::get_alignments(lines[idx + 1]).is_some_and(|a| a.len() == head.len())
+    }
+
+    /// Parse the delimiter row (ex: `| :-- | :-: | --: |`)
+    fn get_alignments(line: &str) -> Option<Vec<Alignment>> {
+        let line = line.trim();
+        if !line.contains('-') || !(line.contains('|') || line.contains(':')) {
+            return None;
+        }
+
+        Table::split_row(line)
+            .iter()
+            .map(|cell| Alignment::new(cell))
+            .collect()
+    }
+
+    /// Split a row into cells, ignoring escaped pipes and pipes inside inline code
+    fn split_row(line: &str) -> Vec<String> {
+        let mut line = line.trim();
+        line = line.strip_prefix('|').unwrap_or(line);
+        if line.ends_with('|') && !line.ends_with("\\|") {
+            line = &line[..line.len() - 1];
+        }
+
+        let mut cells = vec![];
+        let mut cell = String::new();
+        let mut in_code = false;
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'|') => {
+                    cell.push('|');
+                    chars.next();
+                }
+                '`' => {
+                    in_code = !in_code;
+                    cell.push(c);
+                }
+                '|' if !in_code => cells.push(std::mem::take(&mut cell)),
+                _ => cell.push(c),
+            }
+        }
+        cells.push(cell);
+
+        cells.into_iter().map(|c| c.trim().to_string()).collect()
+    }
+
+    pub fn to_html(&self) -> String {
+        let head = self
+            .head
+            .iter()
+            .zip(&self.alignments)
+            .map(|(cell, &alignment)| cell.to_html("th", alignment))
+            .collect::<Vec<String>>()
+            .join("");
+
+        let mut table = format!("<table><thead><tr>{}</tr></thead>", head);
+
+        if !self.rows.is_empty() {
+            let rows = self
+                .rows
+                .iter()
+                .map(|row| {
+                    let cells = row
+                        .iter()
+                        .zip(&self.alignments)
+                        .map(|(cell, &alignment)| cell.to_html("td", alignment))
+                        .collect::<Vec<String>>()
+                        .join("");
+                    format!("<tr>{}</tr>", cells)
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            table.push_str(&format!("<tbody>{}</tbody>", rows));
+        }
+
+        table.push_str("</table>");
+        table
+    }
+}
+
+/// Opening or closing fence of a fenced code block, ex: ```` ```rust ```` or `~~~`
+#[derive(Debug, PartialEq)]
+pub struct CodeFence<'a> {
+    ch: char,
+    len: usize,
+    indent: usize,
+    info: &'a str,
+}
+
+impl<'a> CodeFence<'a> {
+    pub fn new(line: &'a str) -> Option<Self> {
+        let indent = indentation(line);
+        if indent > 3 {
+            return None;
+        }
+
+        let text = line.trim_start();
+        let ch = text.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let len = text.chars().take_while(|c| *c == ch).count();
+        let info = text[len..].trim();
+
+        // Backticks in the info string would be ambiguous with inline code
+        if len < 3 || (ch == '`' && info.contains('`')) {
+            return None;
+        }
+
+        Some(CodeFence {
+            ch,
+            len,
+            indent,
+            info,
+        })
+    }
+
+    /// A closing fence uses the same character, is at least as long and has no info string
+    pub fn is_closed_by(&self, line: &str) -> bool {
+        match CodeFence::new(line) {
+            Some(fence) => fence.ch == self.ch && fence.len >= self.len && fence.info.is_empty(),
+            None => false,
+        }
+    }
+}
+
+/// Info string of a fenced code block, ex: `rust title="main.rs" linenos`
+#[derive(Debug, Default)]
+pub struct CodeInfo {
+    pub lang: Option<String>,
+    /// Remaining words, `key=value` pairs keep their value and `{...}` groups are kept whole
+    pub attributes: Vec<(String, Option<String>)>,
+}
+
+impl CodeInfo {
+    pub fn new(info: &str) -> Self {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+            r#"(?P<key>[^\s={}"']+)=(?:"(?P<double>[^"]*)"|'(?P<single>[^']*)'|(?P<value>[^\s"']*))|(?P<group>\{[^}]*\})|(?P<word>\S+)"#,
+        )
+        .unwrap()
+        });
+
+        let mut code_info = CodeInfo::default();
+        for (idx, caps) in RE.captures_iter(info).enumerate() {
+            if let Some(key) = caps.name("key") {
+                let value = caps
+                    .name("double")
+                    .or_else(|| caps.name("single"))
+                    .or_else(|| caps.name("value"))
+                    .map(|v| v.as_str().to_string());
+                code_info.attributes.push((key.as_str().to_string(), value));
+            } else if let Some(word) = caps.name("word").filter(|_| idx == 0) {
+                code_info.lang = Some(word.as_str().to_string());
+            } else {
+                code_info.attributes.push((caps[0].to_string(), None));
+            }
+        }
+
+        code_info
+    }
+
+    /// Value of a `key=value` attribute
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.as_deref())
+    }
+
+    /// Whether a bare word like `linenos` is present
+    pub fn has(&self, word: &str) -> bool {
+        self.attributes
+            .iter()
+            .any(|(k, v)| k == word && v.is_none())
+    }
+
+    /// Line ranges from `{3,7-9}` groups, `Err` holds the group that couldn't be read
+    pub fn highlighted_lines(&self) -> Result<Vec<RangeInclusive<usize>>, String> {
+        let mut ranges = vec![];
+        let groups = self
+            .attributes
+            .iter()
+            .filter(|(k, v)| k.starts_with('{') && v.is_none());
+
+        for (group, _) in groups {
+            let inner = match group.strip_prefix('{').and_then(|g| g.strip_suffix('}')) {
+                Some(inner) => inner,
+                None => return Err(group.clone()),
+            };
+            for part in inner.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+                let (start, end) = part.split_once('-').unwrap_or((part, part));
+                match (start.trim().parse(), end.trim().parse()) {
+                    (Ok(start), Ok(end)) if start <= end => ranges.push(start..=end),
+                    _ => return Err(group.clone()),
+                }
+            }
+        }
+
+        Ok(ranges)
+    }
+}
+
+#[derive(Debug)]
+pub struct CodeBlock {
+    info: CodeInfo,
+    code: String,
+    /// Escaped, and when the language is known highlighted, content of each line
+    html_lines: Vec<String>,
+    /// 1-based lines to emphasize
+    highlighted: Vec<RangeInclusive<usize>>,
+}
+
+impl CodeBlock {
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        match CodeFence::new(lines[idx]) {
+            Some(fence) => CodeBlock::fenced(lines, idx, fence, ctx),
+            None => CodeBlock::indented(lines, idx),
+        }
+    }
+
+    fn fenced(
+        lines: &[&str],
+        idx: usize,
+        fence: CodeFence,
+        ctx: &Context,
+    ) -> (Option<Self>, usize) {
+        let mut end_idx = idx + 1;
+        while end_idx < lines.len() && !fence.is_closed_by(lines[end_idx]) {
+            end_idx += 1;
+        }
+
+        // Content lines lose as much indentation as the opening fence had
+        let mut code_lines: Vec<String> = lines[idx + 1..end_idx]
+            .iter()
+            .map(|l| strip_indentation(l, fence.indent).to_string())
+            .collect();
+
+        // Like CommonMark, an unclosed fence runs until the end of its container
+        if end_idx == lines.len() {
+            ctx.warn(
+                ctx.line_number(idx),
+                "unclosed code fence, the rest of the block is treated as code",
+            );
+            while code_lines.last().is_some_and(|l| l.trim().is_empty()) {
+                code_lines.pop();
+            }
+        }
+
+        let info = CodeInfo::new(fence.info);
+        let highlighted = info.highlighted_lines().unwrap_or_else(|group| {
+            ctx.warn(
+                ctx.line_number(idx),
+                &format!("invalid highlighted lines `{}`", group),
+            );
+            vec![]
+        });
+
+        // Special languages are rendered from the raw code instead
+        let special = info.lang.as_deref().and_then(SpecialLanguage::find);
+        if let Some(language) = special {
+            ctx.use_language(language.name);
+        }
+
+        let html_lines = match special {
+            Some(_) => vec![],
+            None => info
+                .lang
+                .as_ref()
+                .zip(ctx.highlighter)
+                .and_then(|(lang, highlighter)| highlighter.highlight(lang, &code_lines))
+                .unwrap_or_else(|| code_lines.iter().map(|l| escape_html(l)).collect()),
+        };
+
+        let code_block = CodeBlock {
+            info,
+            code: code_lines.join("\n"),
+            html_lines,
+            highlighted,
+        };
+
+        (Some(code_block), (end_idx + 1).min(lines.len()))
+    }
+
+    /// Lines indented by 4 or more spaces, they can't interrupt a paragraph
+    fn indented(lines: &[&str], idx: usize) -> (Option<Self>, usize) {
+        if lines[idx].trim().is_empty() || indentation(lines[idx]) < 4 {
+            return (None, idx);
+        }
+
+        let mut end_idx = idx;
+        let mut last_code = idx;
+        while end_idx < lines.len() {
+            let line = lines[end_idx];
+            if !line.trim().is_empty() {
+                if indentation(line) < 4 {
+                    break;
+                }
+                last_code = end_idx;
+            }
+            end_idx += 1;
+        }
+
+        // Blank lines after the last indented line aren't part of the code
+        let code_lines = lines[idx..=last_code]
+            .iter()
+            .map(|l| strip_indentation(l, 4))
+            .collect::<Vec<&str>>();
+
+        let code_block = CodeBlock {
+            info: CodeInfo::default(),
+            code: code_lines.join("\n"),
+            html_lines: code_lines.iter().map(|l| escape_html(l)).collect(),
+            highlighted: vec![],
+        };
+
+        (Some(code_block), last_code + 1)
+    }
+
+    pub fn to_html(&self) -> String {
+        if let Some(language) = self.info.lang.as_deref().and_then(SpecialLanguage::find) {
+            return (language.render)(&self.code);
+        }
+
+        let line_numbers = self.info.has("linenos");
+
+        // One block per line, the newline is kept inside so copying the code still works
+        let code = self
+            .html_lines
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| {
+                let number = idx + 1;
+                let class = if self.highlighted.iter().any(|r| r.contains(&number)) {
+                    "code-line highlighted"
+                } else {
+                    "code-line"
+                };
+                let data = if line_numbers {
+                    format!(" data-line='{}'", number)
+                } else {
+                    String::new()
+                };
+                format!("<span class='{}'{}>{}\n</span>", class, data, line)
+            })
+            .collect::<String>();
+
+        // `hl-code` gets the highlight theme colors
+        let pre_class = if line_numbers {
+            " class='hl-code line-numbers'"
+        } else {
+            " class='hl-code'"
+        };
+
+        let pre = match &self.info.lang {
+            Some(lang) => format!(
+                "<pre{}><code class='language-{}'>{}</code></pre>",
+                pre_class,
+                escape_html(lang),
+                code
+            ),
+            None => format!("<pre{}><code>{}</code></pre>", pre_class, code),
+        };
+
+        match self.info.get("title") {
+            Some(title) => format!(
+                "<figure class='code-block'><figcaption class='code-title'>{}</figcaption>{}</figure>",
+                escape_html(title),
+                pre
+            ),
+            None => pre,
+        }
+    }
+}
+
+/// `> text`, the stripped lines are parsed like a page of their own so quotes can nest
+#[derive(Debug)]
+pub struct Quote {
+    children: Vec<Node>,
+}
+
+impl Quote {
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        if Quote::strip_marker(lines[idx]).is_none() {
+            return (None, idx);
+        }
+
+        let (content, end_idx) = Quote::content(lines, idx, ctx);
+        let quote = Quote {
+            children: Document::parse_nodes(&content, ctx.line_number(idx), ctx),
+        };
+
+        (Some(quote), end_idx)
+    }
+
+    /// Remove the `>` markers, unmarked lines continuing a quoted paragraph are lazy continuations
+    fn content<'a>(lines: &[&'a str], idx: usize, ctx: &Context) -> (Vec<&'a str>, usize) {
+        let mut content = vec![];
+        let mut end_idx = idx;
+        while end_idx < lines.len() {
+            if let Some(text) = Quote::strip_marker(lines[end_idx]) {
+                content.push(text);
+            } else if Paragraph::is_lazy_continuation(&content, lines, end_idx, ctx) {
+                content.push(lines[end_idx]);
+            } else {
+                break;
+            }
+            end_idx += 1;
+        }
+
+        (content, end_idx)
+    }
+
+    /// Text after `>` and its optional following space
+    fn strip_marker(line: &str) -> Option<&str> {
+        if indentation(line) > 3 {
+            return None;
+        }
+
+        let text = line.trim_start().strip_prefix('>')?;
+        Some(text.strip_prefix(' ').unwrap_or(text))
+    }
+
+    pub fn to_html(&self) -> String {
+        let children = self
+            .children
+            .iter()
+            .map(|node| node.to_html())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        format!("<blockquote>{}</blockquote>", children)
+    }
+}
+
+/// Kinds available in every book with their default title
+const ADMONITION_KINDS: [(&str, &str); 6] = [
+    ("note", "Note"),
+    ("tip", "Tip"),
+    ("important", "Important"),
+    ("warning", "Warning"),
+    ("caution", "Caution"),
+    ("danger", "Danger"),
+];
+
+/// Callout box, either a `> [!WARNING] title` quote or a `:::warning title` ... `:::` container.
+/// A `+` or `-` right after the kind makes it collapsible, expanded or collapsed.
+#[derive(Debug)]
+pub struct Admonition {
+    kind: String,
+    title: Vec<InlineToken>,
+    /// `Some(open)` for collapsible admonitions
+    collapsible: Option<bool>,
+    children: Vec<Node>,
+}
+
+impl Admonition {
+    pub fn new(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        match Admonition::container(lines, idx, ctx) {
+            (None, _) => Admonition::quote(lines, idx, ctx),
+            admonition => admonition,
+        }
+    }
+
+    fn quote(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+                r"^\[!(?P<kind>[A-Za-z](?:[\w-]*\w)?)\](?P<fold>[+-])?(?:[ \t]+(?P<title>.*))?$",
+            )
+            .unwrap()
+        });
+
+        let caps = match Quote::strip_marker(lines[idx]).and_then(|l| RE.captures(l.trim_end())) {
+            Some(caps) => caps,
+            None => return (None, idx),
+        };
+
+        // Unknown kinds are just quotes, like on GitHub
+        let kind = caps["kind"].to_lowercase();
+        let default_title = match Admonition::default_title(&kind, ctx) {
+            Some(title) => title,
+            None => return (None, idx),
+        };
+
+        let (content, end_idx) = Quote::content(lines, idx, ctx);
+        let admonition = Admonition::from_parts(
+            &kind,
+            &caps,
+            default_title,
+            &content[1..],
+            ctx.line_number(idx),
+            ctx,
+        );
+
+        (Some(admonition), end_idx)
+    }
+
+    fn container(lines: &[&str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+            r"^ {0,3}:{3,}[ \t]*(?P<kind>[A-Za-z](?:[\w-]*\w)?)(?P<fold>[+-])?(?:[ \t]+(?P<title>.*))?$",
+        )
+        .unwrap()
+        });
+
+        let caps = match RE.captures(lines[idx].trim_end()) {
+            Some(caps) => caps,
+            None => return (None, idx),
+        };
+
+        let kind = caps["kind"].to_lowercase();
+        let default_title = Admonition::default_title(&kind, ctx).unwrap_or_else(|| {
+            ctx.warn(
+                ctx.line_number(idx),
+                &format!("unknown admonition kind `{}`", kind),
+            );
+            Admonition::capitalize(&kind)
+        });
+
+        // Containers can nest, colons inside fenced code don't count
+        let mut depth = 1;
+        let mut fence: Option<CodeFence> = None;
+        let mut end_idx = idx + 1;
+        while end_idx < lines.len() {
+            let line = lines[end_idx];
+            if let Some(open) = &fence {
+                if open.is_closed_by(line) {
+                    fence = None;
+                }
+            } else if let Some(open) = CodeFence::new(line) {
+                fence = Some(open);
+            } else if RE.is_match(line.trim_end()) {
+                depth += 1;
+            } else if Admonition::is_closing(line) {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            end_idx += 1;
+        }
+
+        if end_idx == lines.len() {
+            ctx.warn(
+                ctx.line_number(idx),
+                "unclosed admonition, the rest of the block is inside it",
+            );
+        }
+
+        let content = &lines[idx + 1..end_idx];
+        let admonition = Admonition::from_parts(
+            &kind,
+            &caps,
+            default_title,
+            content,
+            ctx.line_number(idx),
+            ctx,
+        );
+
+        (Some(admonition), (end_idx + 1).min(lines.len()))
+    }
+
+    fn from_parts(
+        kind: &str,
+        caps: &regex::Captures,
+        default_title: String,
+        content: &[&str],
+        line: usize,
+        ctx: &Context,
+    ) -> Self {
+        let title = caps
+            .name("title")
+            .map(|t| t.as_str().trim())
+            .filter(|t| !t.is_empty())
+            .map_or(default_title, |t| t.to_string());
+
+        Admonition {
+            kind: kind.to_string(),
+            title: InlineToken::parse(&title, line, ctx),
+            collapsible: caps.name("fold").map(|fold| fold.as_str() == "+"),
+            // The content starts on the line after the title
+            children: Document::parse_nodes(content, line + 1, ctx),
+        }
+    }
+
+    /// Whether the line opens or closes a `:::` container
+    pub fn is_fence(line: &str) -> bool {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^ {0,3}:{3,}[ \t]*(?:[A-Za-z](?:[\w-]*\w)?[+-]?(?:[ \t].*)?)?$").unwrap()
+        });
+        RE.is_match(line.trim_end())
+    }
+
+    fn is_closing(line: &str) -> bool {
+        static RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^ {0,3}:{3,}[ \t]*$").unwrap());
+        RE.is_match(line)
+    }
+
+    /// Title of a built-in or `book.json` kind, `None` for unknown kinds
+    fn default_title(kind: &str, ctx: &Context) -> Option<String> {
+        if let Some((_, title)) = ADMONITION_KINDS.iter().find(|(k, _)| *k == kind) {
+            return Some(title.to_string());
+        }
+
+        let (name, custom) = ctx
+            .config
+            .admonitions
+            .iter()
+            .find(|(k, _)| k.to_lowercase() == kind)?;
+        Some(
+            custom
+                .title
+                .clone()
+                .unwrap_or_else(|| Admonition::capitalize(name)),
+        )
+    }
+
+    fn capitalize(text: &str) -> String {
+        let mut chars = text.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    }
+
+    pub fn to_html(&self) -> String {
+        let title = InlineToken::render(&self.title);
+        let children = self
+            .children
+            .iter()
+            .map(|node| node.to_html())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        match self.collapsible {
+            Some(open) => format!(
+                "<details class='admonition admonition-{}'{}><summary class='admonition-title'>{}</summary>\n{}</details>",
+                self.kind,
+                if open { " open" } else { "" },
+                title,
+                children
+            ),
+            None => format!(
+                "<div class='admonition admonition-{}'><p class='admonition-title'>{}</p>\n{}</div>",
+                self.kind, title, children
+            ),
+        }
+    }
+}
+
+/// `[label]: destination "title"`, collected before parsing and never rendered
+#[derive(Debug)]
+pub struct LinkDefinition {
+    pub label: String,
+    pub link: Link,
+}
+
+impl LinkDefinition {
+    pub fn new(lines: &[&str], idx: usize) -> (Option<Self>, usize) {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+            r#"^ {0,3}\[(?P<label>(?:[^\[\]\\]|\\.)+)\]:\s*(?:<(?P<angled>[^<>\n]*)>|(?P<href>\S+))(?P<title>\s+(?:"[^"]*"|'[^']*'|\([^()]*\)))?\s*$"#,
+        )
+        .unwrap()
+        });
+
+        // The destination can also be on the next line
+        let mut end_idx = idx + 1;
+        let mut line = lines[idx].to_string();
+        if line.trim_end().ends_with("]:") && end_idx < lines.len() {
+            line = format!("{} {}", line.trim_end(), lines[end_idx].trim());
+            end_idx += 1;
+        }
+
+        // `[^label]:` is a footnote
+        let caps = match RE.captures(&line) {
+            Some(caps) if !caps["label"].trim().is_empty() && !caps["label"].starts_with('^') => {
+                caps
+            }
+            _ => return (None, idx),
+        };
+
+        let href = caps.name("angled").or_else(|| caps.name("href"));
+        let href = href.map_or("", |m| m.as_str()).to_string();
+
+        // The title can also be on the next line
+        let title = match caps.name("title") {
+            Some(title) => LinkDefinition::get_title(title.as_str()),
+            None if end_idx < lines.len() => {
+                let title = LinkDefinition::get_title(lines[end_idx]);
+                if title.is_some() {
+                    end_idx += 1;
+                }
+                title
+            }
+            None => None,
+        };
+
+        let definition = LinkDefinition {
+            label: caps["label"].to_string(),
+            link: Link { href, title },
+        };
+
+        (Some(definition), end_idx)
+    }
+
+    fn get_title(text: &str) -> Option<String> {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^\s*(?:"(?P<dq>[^"]*)"|'(?P<sq>[^']*)'|\((?P<pq>[^()]*)\))\s*$"#).unwrap()
+        });
+        let caps = RE.captures(text)?;
+        let title = caps
+            .name("dq")
+            .or_else(|| caps.name("sq"))
+            .or_else(|| caps.name("pq"))?;
+
+        Some(title.as_str().to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TocEntry {
+    id: String,
+    text: String,
+    level: u8,
+    children: Vec<TocEntry>,
+}
+
+/// Outline of the page headings, also rendered where a `[TOC]` marker is placed
+#[derive(Debug, Clone, Default)]
+pub struct TableOfContents {
+    entries: Vec<TocEntry>,
+}
+
+impl TableOfContents {
+    pub fn new(nodes: &[Node], min_depth: u8, max_depth: u8) -> Self {
+        let headings = nodes.iter().filter_map(|node| match node {
+            Node::Heading(h) if (min_depth..=max_depth).contains(&h.level()) => Some(h),
+            _ => None,
+        });
+
+        // Open entries from the outermost to the innermost, a heading closes the deeper or equal ones
+        let mut entries: Vec<TocEntry> = vec![];
+        let mut stack: Vec<TocEntry> = vec![];
+        for heading in headings {
+            let entry = TocEntry {
+                id: heading.id().to_string(),
+                text: heading.text(),
+                level: heading.level(),
+                children: vec![],
+            };
+
+            while stack.last().is_some_and(|open| open.level >= entry.level) {
+                TableOfContents::close(&mut stack, &mut entries);
+            }
+            stack.push(entry);
+        }
+        while !stack.is_empty() {
+            TableOfContents::close(&mut stack, &mut entries);
+        }
+
+        TableOfContents { entries }
+    }
+
+    fn close(stack: &mut Vec<TocEntry>, entries: &mut Vec<TocEntry>) {
+        let entry = stack.pop().unwrap();
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(entry),
+            None => entries.push(entry),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn list_html(entries: &[TocEntry]) -> String {
+        let items = entries
+            .iter()
+            .map(|entry| {
+                let children = if entry.children.is_empty() {
+                    String::new()
+                } else {
+                    TableOfContents::list_html(&entry.children)
+                };
+                format!(
+                    "<li><a href='#{}'>{}</a>{}</li>",
+                    escape_html(&entry.id),
+                    escape_html(&entry.text),
+                    children
+                )
+            })
+            .collect::<String>();
+
+        format!("<ul class='toc-list'>{}</ul>", items)
+    }
+
+    /// Right-hand column of the page
+    pub fn sidebar_html(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+
+        format!(
+            "<nav class='toc'><p class='toc-title'>On this page</p>{}</nav>",
+            TableOfContents::list_html(&self.entries)
+        )
+    }
+
+    /// Outline inlined in the page by `[TOC]`
+    pub fn to_html(&self) -> String {
+        format!(
+            "<nav class='toc-inline'>{}</nav>",
+            TableOfContents::list_html(&self.entries)
+        )
+    }
+}
+
+/// `[^label]: text`, following lines indented by 4 spaces belong to the footnote too
+#[derive(Debug)]
+pub struct FootnoteDefinition<'a> {
+    pub label: String,
+    pub lines: Vec<&'a str>,
+}
+
+impl<'a> FootnoteDefinition<'a> {
+    pub fn new(lines: &[&'a str], idx: usize, ctx: &Context) -> (Option<Self>, usize) {
+        static RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^ {0,3}\[\^(?P<label>[^\]\s]+)\]:[ \t]*").unwrap());
+        let caps = match RE.captures(lines[idx]) {
+            Some(caps) => caps,
+            None => return (None, idx),
+        };
+
+        let mut content = vec![&lines[idx][caps[0].len()..]];
+        let mut end_idx = idx + 1;
+        while end_idx < lines.len() {
+            let line = lines[end_idx];
+            if line.trim().is_empty() {
+                // Blank lines are kept only if indented content follows
+                let next = lines[end_idx..].iter().position(|l| !l.trim().is_empty());
+                match next.map(|offset| end_idx + offset) {
+                    Some(next) if indentation(lines[next]) >= 4 => {
+                        content.resize(content.len() + next - end_idx, "");
+                        end_idx = next;
+                        continue;
+                    }
+                    _ => break,
+                }
+            } else if indentation(line) >= 4 {
+                content.push(strip_indentation(line, 4));
+            } else if !RE.is_match(line)
+                && Paragraph::is_lazy_continuation(&content, lines, end_idx, ctx)
+            {
+                content.push(line);
+            } else {
+                break;
+            }
+            end_idx += 1;
+        }
+
+        let definition = FootnoteDefinition {
+            label: caps["label"].to_string(),
+            lines: content,
+        };
+
+        (Some(definition), end_idx)
+    }
+}
+
+/// A footnote of the page, rendered in the footnotes section with links back to its references
+#[derive(Debug)]
+pub struct Footnote {
+    number: usize,
+    refs: usize,
+    children: Vec<Node>,
+}
+
+impl Footnote {
+    pub fn new(number: usize, refs: usize, children: Vec<Node>) -> Self {
+        Footnote {
+            number,
+            refs,
+            children,
+        }
+    }
+
+    pub fn to_html(&self) -> String {
+        let backrefs = (1..=self.refs)
+            .map(|occurrence| {
+                let label = match occurrence {
+                    1 => String::new(),
+                    _ => format!("<sup>{}</sup>", occurrence),
+                };
+                format!(
+                    "<a href='#{}' class='footnote-backref'>&#8617;&#xFE0E;{}</a>",
+                    InlineToken::footnote_ref_id(self.number, occurrence),
+                    label
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let mut content = self
+            .children
+            .iter()
+            .map(|node| node.to_html())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        // Back links go at the end of the last paragraph when there is one
+        match content.strip_suffix("</p>") {
+            Some(text) => content = format!("{} {}</p>", text, backrefs),
+            None => content.push_str(&format!("<p>{}</p>", backrefs)),
+        }
+
+        format!("<li id='fn-{}'>{}</li>", self.number, content)
+    }
+}
+
+#[derive(Debug)]
+pub struct LineBreak;
 
 impl LineBreak {
     pub fn new(line: &str) -> Option<LineBreak> {
-        if line.trim().len() == 0 {
+        if line.trim().is_empty() {
             return Some(LineBreak);
         }
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Admonition, CodeBlock, DefinitionList, Heading, HtmlBlock, List, Paragraph, Quote, Table,
+    };
+    use crate::{
+        config::{AdmonitionKind, Config, RawHtml},
+        context::Context,
+        documents::Document,
+        node::Node,
+    };
+
+    #[test]
+    fn it_merge_paragraph_lines_with_hard_breaks() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = [
+            "First line",
+            "  **second** line  ",
+            "third line\\",
+            "last line",
+            "- list",
+        ];
+
+        let (paragraph, idx) = Paragraph::new(&lines, 0, &ctx);
+        assert_eq!(idx, 4);
+        assert_eq!(
+            paragraph.unwrap().to_html(),
+            "<p>First line\n<strong>second</strong> line<br/>\nthird line<br/>\nlast line</p>"
+        );
+    }
+
+    #[test]
+    fn it_escape_text_code_and_attributes() {
+        let mut config = Config::new();
+        let ctx = Context::new(&config, "");
+
+        let lines = ["a <b> & `Vec<String>` [x](/?a='1'&b=2)"];
+        let (paragraph, _) = Paragraph::new(&lines, 0, &ctx);
+        assert_eq!(
+            paragraph.unwrap().to_html(),
+            "<p>a &lt;b&gt; &amp; <span class='inline-code'>Vec&lt;String&gt;</span> <a href='/?a=&#39;1&#39;&amp;b=2'>x</a></p>"
+        );
+
+        let lines = ["```rust", "let v: Vec<u8> = vec![];", "```"];
+        let (code, _) = CodeBlock::new(&lines, 0, &ctx);
+        assert_eq!(
+            code.unwrap().to_html(),
+            "<pre class='hl-code'><code class='language-rust'><span class='code-line'>let v: Vec&lt;u8&gt; = vec![];\n</span></code></pre>"
+        );
+
+        config.raw_html = RawHtml::Allow;
+        let ctx = Context::new(&config, "");
+        let lines = ["<b>raw</b> `<b>`"];
+        let (paragraph, _) = Paragraph::new(&lines, 0, &ctx);
+        assert_eq!(
+            paragraph.unwrap().to_html(),
+            "<p><b>raw</b> <span class='inline-code'>&lt;b&gt;</span></p>"
+        );
+    }
+
+    #[test]
+    fn it_merge_quote_and_list_lazy_lines() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = ["> one", "two", ">", "> three", "", "after"];
+
+        let (quote, idx) = Quote::new(&lines, 0, &ctx);
+        assert_eq!(idx, 4);
+        assert_eq!(
+            quote.unwrap().to_html(),
+            "<blockquote><p>one\ntwo</p>\n<p>three</p></blockquote>"
+        );
+
+        let lines = ["- one", "two", "- three"];
+        let (list, _) = List::new(&lines, 0, &ctx);
+        assert_eq!(
+            list.unwrap().to_html(),
+            "<ul><li>one\ntwo</li>\n<li>three</li></ul>"
+        );
+    }
+
+    #[test]
+    fn it_parse_nested_and_mixed_lists() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = [
+            "- Rust",
+            "  1. Ownership",
+            "  2. Borrowing",
+            "     - `&T`",
+            "- Go",
+            "",
+            "Paragraph",
+        ];
+
+        let (list, idx) = List::new(&lines, 0, &ctx);
+        assert_eq!(idx, 5);
+        assert_eq!(
+            list.unwrap().to_html(),
+            "<ul><li>Rust\n<ol><li>Ownership</li>\n<li>Borrowing\n<ul><li><span class='inline-code'>&amp;T</span></li></ul></li></ol></li>\n<li>Go</li></ul>"
+        );
+    }
+
+    #[test]
+    fn it_keep_ordered_list_start_and_loose_items() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = [
+            "3. Three",
+            "",
+            "4. Four",
+            "",
+            "   ```rust",
+            "   let x = 1;",
+            "   ```",
+        ];
+
+        let (list, idx) = List::new(&lines, 0, &ctx);
+        assert_eq!(idx, 7);
+        assert_eq!(
+            list.unwrap().to_html(),
+            "<ol start='3'><li><p>Three</p></li>\n<li><p>Four</p>\n<pre class='hl-code'><code class='language-rust'><span class='code-line'>let x = 1;\n</span></code></pre></li></ol>"
+        );
+    }
+
+    #[test]
+    fn it_render_task_list_items() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = ["- [ ] todo", "- [x] **done**", "- [] not a task"];
+
+        let (list, _) = List::new(&lines, 0, &ctx);
+        assert_eq!(
+            list.unwrap().to_html(),
+            "<ul><li class='task-list-item'><input type='checkbox' class='task-list-item-checkbox' disabled/>todo</li>\n\
+            <li class='task-list-item'><input type='checkbox' class='task-list-item-checkbox' disabled checked/><strong>done</strong></li>\n\
+            <li>[] not a task</li></ul>"
+        );
+    }
+
+    #[test]
+    fn it_start_new_list_when_marker_changes() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = ["- a", "1. b"];
+
+        let (list, idx) = List::new(&lines, 0, &ctx);
+        assert_eq!(idx, 1);
+        assert_eq!(list.unwrap().to_html(), "<ul><li>a</li></ul>");
+    }
+
+    #[test]
+    fn it_parse_table_with_alignment() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = [
+            "| Name | Age | City |",
+            "| :--- | :-: | ---: |",
+            "| **Ahmed** | 24 | Cairo |",
+            "| Sara | `a \\| b` |",
+            "",
+        ];
+
+        let (table, idx) = Table::new(&lines, 0, &ctx);
+        assert_eq!(idx, 4);
+        assert_eq!(
+            table.unwrap().to_html(),
+            "<table><thead><tr><th style='text-align: left'>Name</th><th style='text-align: center'>Age</th><th style='text-align: right'>City</th></tr></thead>\
+            <tbody><tr><td style='text-align: left'><strong>Ahmed</strong></td><td style='text-align: center'>24</td><td style='text-align: right'>Cairo</td></tr>\n\
+            <tr><td style='text-align: left'>Sara</td><td style='text-align: center'><span class='inline-code'>a | b</span></td><td style='text-align: right'></td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn it_reject_table_without_delimiter_row() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = ["| a | b |", "| c | d |"];
+        assert!(Table::new(&lines, 0, &ctx).0.is_none());
+
+        let lines = ["| a | b |", "| --- |"];
+        assert!(Table::new(&lines, 0, &ctx).0.is_none());
+    }
+
+    #[test]
+    fn it_parse_fenced_and_indented_code() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+
+        let lines = [
+            "~~~~ rust title=\"main.rs\" {3,7-9} linenos",
+            "```",
+            "~~~",
+            "~~~~~",
+            "after",
+        ];
+        let (code, end_idx) = CodeBlock::new(&lines, 0, &ctx);
+        let code = code.unwrap();
+        assert_eq!(end_idx, 4);
+        assert_eq!(code.info.lang.as_deref(), Some("rust"));
+        assert_eq!(
+            code.info.attributes,
+            [
+                ("title".to_string(), Some("main.rs".to_string())),
+                ("{3,7-9}".to_string(), None),
+                ("linenos".to_string(), None)
+            ]
+        );
+        assert_eq!(
+            code.to_html(),
+            "<figure class='code-block'><figcaption class='code-title'>main.rs</figcaption><pre class='hl-code line-numbers'><code class='language-rust'><span class='code-line' data-line='1'>```\n</span><span class='code-line' data-line='2'>~~~\n</span></code></pre></figure>"
+        );
+
+        let lines = ["  ```", "   a", " b", "  ```"];
+        let (code, _) = CodeBlock::new(&lines, 0, &ctx);
+        assert_eq!(
+            code.unwrap().to_html(),
+            "<pre class='hl-code'><code><span class='code-line'> a\n</span><span class='code-line'>b\n</span></code></pre>"
+        );
+
+        let lines = [
+            "    fn main() {",
+            "",
+            "\t  println!();",
+            "    }",
+            "",
+            "text",
+        ];
+        let (code, end_idx) = CodeBlock::new(&lines, 0, &ctx);
+        assert_eq!(end_idx, 4);
+        assert_eq!(
+            code.unwrap().to_html(),
+            "<pre class='hl-code'><code><span class='code-line'>fn main() {\n</span><span class='code-line'>\n</span><span class='code-line'>  println!();\n</span><span class='code-line'>}\n</span></code></pre>"
+        );
+
+        // Indented lines can't interrupt a paragraph
+        let lines = [
+            "text", "    more", "", "- item", "", "  ```", "  code", "  ```",
+        ];
+        let html = Document::parse_nodes(&lines, 1, &ctx)
+            .iter()
+            .map(|n| n.to_html())
+            .collect::<Vec<String>>()
+            .join("\n");
+        assert_eq!(
+            html,
+            "<p>text\nmore</p>\n<ul><li><p>item</p>\n<pre class='hl-code'><code><span class='code-line'>code\n</span></code></pre></li></ul>"
+        );
+    }
+
+    #[test]
+    fn it_report_unclosed_fences() {
+        let config = Config::new();
+        let doc = Document::parse("# Title\n\n```rust\nfn main() {}\n", &config, None);
+        assert_eq!(doc.diagnostics.len(), 1);
+        assert_eq!(doc.diagnostics[0].line, 3);
+    }
+
+    #[test]
+    fn it_render_code_line_features() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+
+        let lines = ["```rust {1,3-4}", "a", "b", "c", "d", "```"];
+        let (code, _) = CodeBlock::new(&lines, 0, &ctx);
+        assert_eq!(
+            code.unwrap().to_html(),
+            "<pre class='hl-code'><code class='language-rust'><span class='code-line highlighted'>a\n</span><span class='code-line'>b\n</span><span class='code-line highlighted'>c\n</span><span class='code-line highlighted'>d\n</span></code></pre>"
+        );
+
+        let doc = Document::parse("```rust {2-1}\na\n```", &config, None);
+        assert_eq!(
+            doc.diagnostics[0].message,
+            "invalid highlighted lines `{2-1}`"
+        );
+
+        let doc = Document::parse("```rust {\nx\n```", &config, None);
+        assert_eq!(doc.diagnostics[0].message, "invalid highlighted lines `{`");
+    }
+
+    #[test]
+    fn it_parse_nested_quotes_with_blocks() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = [
+            "> # Title",
+            "> > nested",
+            "lazy",
+            ">",
+            ">     code",
+            ">",
+            "> - item",
+        ];
+
+        let (quote, idx) = Quote::new(&lines, 0, &ctx);
+        assert_eq!(idx, 7);
+        assert_eq!(
+            quote.unwrap().to_html(),
+            "<blockquote><h1 id='title'>Title<a class='heading-anchor' href='#title' aria-label='Permalink'>#</a></h1>\n<blockquote><p>nested\nlazy</p></blockquote>\n<pre class='hl-code'><code><span class='code-line'>code\n</span></code></pre>\n<ul><li>item</li></ul></blockquote>"
+        );
+    }
+
+    #[test]
+    fn it_only_continue_paragraphs_lazily() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+
+        // Headings and fenced code are closed blocks, the next line leaves the quote
+        for lines in [["> # H", "text"], ["> ```", "text"]] {
+            let (_, idx) = Quote::new(&lines, 0, &ctx);
+            assert_eq!(idx, 1);
+        }
+        let lines = ["> ```", "> a", "> ```", "text"];
+        assert_eq!(Quote::new(&lines, 0, &ctx).1, 3);
+
+        // A paragraph in a nested list item or quote is still open
+        let lines = ["> - item", "text"];
+        let (quote, idx) = Quote::new(&lines, 0, &ctx);
+        assert_eq!(idx, 2);
+        assert_eq!(
+            quote.unwrap().to_html(),
+            "<blockquote><ul><li>item\ntext</li></ul></blockquote>"
+        );
+        let lines = ["- > # H", "text"];
+        assert_eq!(List::new(&lines, 0, &ctx).1, 1);
+    }
+
+    #[test]
+    fn it_parse_admonitions() {
+        let mut config = Config::new();
+        config
+            .admonitions
+            .insert("exercise".to_string(), AdmonitionKind::default());
+        let ctx = Context::new(&config, "");
+
+        let lines = ["> [!WARNING]", "> Mind the **gap**", "after"];
+        let (admonition, idx) = Admonition::new(&lines, 0, &ctx);
+        assert_eq!(idx, 3);
+        assert_eq!(
+            admonition.unwrap().to_html(),
+            "<div class='admonition admonition-warning'><p class='admonition-title'>Warning</p>\n<p>Mind the <strong>gap</strong>\nafter</p></div>"
+        );
+
+        let lines = [
+            ":::Exercise- Try *it*",
+            "::: tip",
+            "```",
+            ":::",
+            "```",
+            ":::",
+            ":::",
+            "after",
+        ];
+        let (admonition, idx) = Admonition::new(&lines, 0, &ctx);
+        assert_eq!(idx, 7);
+        assert_eq!(
+            admonition.unwrap().to_html(),
+            "<details class='admonition admonition-exercise'><summary class='admonition-title'>Try <em>it</em></summary>\n<div class='admonition admonition-tip'><p class='admonition-title'>Tip</p>\n<pre class='hl-code'><code><span class='code-line'>:::\n</span></code></pre></div></details>"
+        );
+
+        // Unknown kinds stay quotes
+        let lines = ["> [!FOO]"];
+        assert!(Admonition::new(&lines, 0, &ctx).0.is_none());
+        assert!(Quote::new(&lines, 0, &ctx).0.is_some());
+    }
+
+    #[test]
+    fn it_give_headings_unique_ids() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let ids = [
+            "## Ownership & *Borrowing*",
+            "## Ownership & Borrowing",
+            "# Ownership & Borrowing-1",
+            "### Привет, мир!",
+            "## Custom {#ownership-borrowing-2 .lead .wide}",
+            "## Literal {not attrs}",
+        ]
+        .map(|line| Heading::new(&[line], 0, &ctx).unwrap().id);
+
+        assert_eq!(
+            ids,
+            [
+                "ownership-borrowing",
+                "ownership-borrowing-1",
+                "ownership-borrowing-1-1",
+                "привет-мир",
+                "ownership-borrowing-2",
+                "literal-not-attrs"
+            ]
+        );
+
+        let heading = Heading::new(&["## Title {.lead}"], 0, &ctx).unwrap();
+        assert_eq!(
+            heading.to_html(),
+            "<h2 id='title' class='lead'>Title<a class='heading-anchor' href='#title' aria-label='Permalink'>#</a></h2>"
+        );
+    }
+
+    #[test]
+    fn it_parse_html_blocks() {
+        let mut config = Config::new();
+        let lines = ["<div class='note'>", "", "*text*", "", "</div>"];
+        let ctx = Context::new(&config, "");
+        let nodes = Document::parse_nodes(&lines, 1, &ctx);
+        assert!(matches!(nodes[0], Node::Paragraph(_)));
+
+        // Escaped tags don't break a paragraph, nor does display math when it's disabled
+        let nodes = Document::parse_nodes(&["para", "<div>", "more"], 1, &ctx);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].to_html(), "<p>para\n&lt;div&gt;\nmore</p>");
+        config.extensions.math = false;
+        let ctx = Context::new(&config, "");
+        assert_eq!(
+            Document::parse_nodes(&["para", "$$ text"], 1, &ctx).len(),
+            1
+        );
+        config.extensions.math = true;
+
+        config.raw_html = RawHtml::Allow;
+        let ctx = Context::new(&config, "");
+        let nodes = Document::parse_nodes(&lines, 1, &ctx);
+        let html = nodes.iter().map(|n| n.to_html()).collect::<Vec<_>>();
+        assert_eq!(
+            html,
+            ["<div class='note'>", "<p><em>text</em></p>", "</div>"]
+        );
+
+        let lines = ["<script>", "", "let a = 1 < 2;", "</script>", "after"];
+        let (block, idx) = HtmlBlock::new(&lines, 0, &ctx);
+        assert_eq!(idx, 4);
+        assert_eq!(block.unwrap().to_html(), lines[..4].join("\n"));
+
+        // A lone tag only starts a block outside of a paragraph
+        let lines = ["text", "<custom-tag>", "more"];
+        let nodes = Document::parse_nodes(&lines, 1, &ctx);
+        assert_eq!(nodes.len(), 1);
+        assert!(HtmlBlock::new(&lines, 1, &ctx).0.is_some());
+
+        config.raw_html = RawHtml::Sanitize;
+        let ctx = Context::new(&config, "");
+        let lines = [
+            "<details onclick='x()'>",
+            "<summary>More</summary>",
+            "<script>a()</script>",
+            "</details>",
+        ];
+        let (block, _) = HtmlBlock::new(&lines, 0, &ctx);
+        assert_eq!(
+            block.unwrap().to_html(),
+            "<details>\n<summary>More</summary>\n\n</details>"
+        );
+        let (paragraph, _) = Paragraph::new(&["a <span onmouseover='x()'>b</span><form>"], 0, &ctx);
+        assert_eq!(paragraph.unwrap().to_html(), "<p>a <span>b</span></p>");
+    }
+
+    #[test]
+    fn it_parse_definition_lists() {
+        let config = Config::new();
+        let ctx = Context::new(&config, "");
+        let lines = [
+            "Apple",
+            "Pomme",
+            ": A company",
+            ": A *fruit*",
+            "lazy line",
+            "",
+            "Orange",
+            ": Another fruit",
+            "",
+            "After",
+        ];
+
+        let (list, idx) = DefinitionList::new(&lines, 0, &ctx);
+        assert_eq!(idx, 8);
+        assert_eq!(
+            list.unwrap().to_html(),
+            "<dl><dt>Apple</dt>\n<dt>Pomme</dt>\n<dd>A company</dd>\n<dd>A <em>fruit</em>\nlazy line</dd>\n<dt>Orange</dt>\n<dd>Another fruit</dd></dl>"
+        );
+
+        let lines = [
+            "Term",
+            "",
+            ":   First paragraph",
+            "",
+            "    Second one",
+            "",
+            "    ```",
+            "    code",
+            "    ```",
+        ];
+        let (list, idx) = DefinitionList::new(&lines, 0, &ctx);
+        assert_eq!(idx, lines.len());
+        assert_eq!(
+            list.unwrap().to_html(),
+            "<dl><dt>Term</dt>\n<dd><p>First paragraph</p>\n<p>Second one</p>\n<pre class='hl-code'><code><span class='code-line'>code\n</span></code></pre></dd></dl>"
+        );
+
+        let lines = ["Not a term", "", "text"];
+        assert!(DefinitionList::new(&lines, 0, &ctx).0.is_none());
+    }
+}
@@ -0,0 +1,184 @@
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+use crate::{config::Typography, inline::InlineToken};
+
+/// Opening and closing double quotes, then opening and closing single quotes
+fn quotes(locale: &str) -> [&'static str; 4] {
+    let language = locale.split(['-', '_']).next().unwrap_or_default();
+    match language.to_lowercase().as_str() {
+        "fr" => ["«\u{202F}", "\u{202F}»", "‹\u{202F}", "\u{202F}›"],
+        "de" | "cs" | "da" => ["„", "“", "‚", "‘"],
+        "es" | "it" | "pt" => ["«", "»", "“", "”"],
+        "ru" | "uk" => ["«", "»", "„", "“"],
+        "pl" | "hu" | "ro" => ["„", "”", "‚", "’"],
+        "sv" | "fi" => ["”", "”", "’", "’"],
+        "ja" | "zh" => ["「", "」", "『", "』"],
+        _ => ["“", "”", "‘", "’"],
+    }
+}
+
+/// Replaces emoji shortcodes and straight punctuation in the text tokens,
+/// code, math, HTML and links written as URLs are kept as is
+pub struct Typographer<'a> {
+    config: &'a Typography,
+    quotes: [&'static str; 4],
+    /// Last character before the text being transformed, tells opening quotes from closing ones
+    prev: Option<char>,
+}
+
+impl<'a> Typographer<'a> {
+    pub fn new(config: &'a Typography) -> Self {
+        Typographer {
+            config,
+            quotes: quotes(&config.locale),
+            prev: None,
+        }
+    }
+
+    pub fn apply(&mut self, tokens: &mut [InlineToken]) {
+        for token in tokens {
+            match token {
+                InlineToken::Text(text) => *text = self.text(text),
+                InlineToken::Emphasis(children)
+                | InlineToken::Strong(children)
+                | InlineToken::Strikethrough(children)
+                | InlineToken::Highlight(children)
+                | InlineToken::Superscript(children)
+                | InlineToken::Subscript(children)
+                | InlineToken::Link { children, .. } => self.apply(children),
+                InlineToken::Code(text)
+                | InlineToken::Autolink(text)
+                | InlineToken::Email(text)
+                | InlineToken::Math { tex: text, .. } => self.prev = text.chars().next_back(),
+                InlineToken::SoftBreak | InlineToken::HardBreak => self.prev = Some(' '),
+                InlineToken::Html(_) => self.prev = None,
+                InlineToken::Kbd(_)
+                | InlineToken::Image { .. }
+                | InlineToken::FootnoteRef { .. } => self.prev = Some('x'),
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        // Without autolinks, bare URLs are still text and must stay usable
+        static RE_URL: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"(?:https?://|www\.)[^\s<]+").unwrap());
+
+        let mut transformed = String::with_capacity(text.len());
+        let mut last = 0;
+        for url in RE_URL.find_iter(text) {
+            transformed.push_str(&self.words(&text[last..url.start()]));
+            transformed.push_str(url.as_str());
+            self.prev = url.as_str().chars().next_back();
+            last = url.end();
+        }
+        transformed.push_str(&self.words(&text[last..]));
+        transformed
+    }
+
+    fn words(&mut self, text: &str) -> String {
+        let mut text = text.to_string();
+        if self.config.emoji {
+            static RE_SHORTCODE: LazyLock<Regex> =
+                LazyLock::new(|| Regex::new(r":([a-z0-9_+-]+):").unwrap());
+            text = RE_SHORTCODE
+                .replace_all(&text, |caps: &Captures| {
+                    match emojis::get_by_shortcode(&caps[1]) {
+                        Some(emoji) => emoji.as_str().to_string(),
+                        None => caps[0].to_string(),
+                    }
+                })
+                .to_string();
+        }
+        if !self.config.smart_punctuation {
+            self.prev = text.chars().next_back().or(self.prev);
+            return text;
+        }
+
+        let text = text
+            .replace("...", "…")
+            .replace("---", "—")
+            .replace("--", "–");
+        let chars: Vec<char> = text.chars().collect();
+        let mut transformed = String::with_capacity(text.len());
+        for (i, &c) in chars.iter().enumerate() {
+            let next = chars.get(i + 1).copied();
+            let opening = self
+                .prev
+                .is_none_or(|p| p.is_whitespace() || "([{<-–—/“‘«„".contains(p));
+
+            match c {
+                '"' if opening => transformed.push_str(self.quotes[0]),
+                '"' => transformed.push_str(self.quotes[1]),
+                // Apostrophes, ex: `don't` or `'90s`
+                '\'' if self.prev.is_some_and(char::is_alphanumeric)
+                    && next.is_some_and(char::is_alphanumeric)
+                    || opening && next.is_some_and(|n| n.is_ascii_digit()) =>
+                {
+                    transformed.push('’')
+                }
+                '\'' if opening => transformed.push_str(self.quotes[2]),
+                '\'' => transformed.push_str(self.quotes[3]),
+                _ => transformed.push(c),
+            }
+            self.prev = Some(c);
+        }
+        transformed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transform(tokens: &mut [InlineToken], locale: &str) {
+        let config = Typography {
+            emoji: true,
+            smart_punctuation: true,
+            locale: locale.to_string(),
+        };
+        Typographer::new(&config).apply(tokens);
+    }
+
+    #[test]
+    fn it_replace_emoji_and_punctuation() {
+        let mut tokens = vec![InlineToken::Text(
+            "\"Don't panic\" -- it's 'fine'... :tada: :nope: 1990---2000".to_string(),
+        )];
+        transform(&mut tokens, "en");
+        assert_eq!(
+            tokens,
+            vec![InlineToken::Text(
+                "“Don’t panic” – it’s ‘fine’… 🎉 :nope: 1990—2000".to_string()
+            )]
+        );
+
+        let mut tokens = vec![InlineToken::Text("Il a dit \"oui\"".to_string())];
+        transform(&mut tokens, "fr-FR");
+        assert_eq!(
+            tokens,
+            vec![InlineToken::Text(
+                "Il a dit «\u{202F}oui\u{202F}»".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn it_keep_code_and_urls() {
+        let mut tokens = vec![
+            InlineToken::Text("Run \"".to_string()),
+            InlineToken::Code("a -- \"b\"".to_string()),
+            InlineToken::Text("\" on https://example.com/a--b...".to_string()),
+        ];
+        transform(&mut tokens, "en");
+        assert_eq!(
+            tokens,
+            vec![
+                InlineToken::Text("Run “".to_string()),
+                InlineToken::Code("a -- \"b\"".to_string()),
+                InlineToken::Text("” on https://example.com/a--b...".to_string()),
+            ]
+        );
+    }
+}
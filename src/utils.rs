@@ -1,19 +1,38 @@
 use std::{path::Path, fs, io};
 
-use regex::Regex;
-
-pub fn replace_html(doc: &str, content: &str, sidebar: &str) -> String {
-    let re_content = Regex::new(r#"<div class="content">(?P<text>[\s\S][^<]*?)</div>"#).unwrap();
-    let re_sidebar = Regex::new(r#"<div class="sidebar">(?P<text>[\s\S][^<]*?)</div>"#).unwrap();
-
-    let content_caps = re_content.captures(doc).expect("Tag not found");
-    let sidebar_caps = re_sidebar.captures(doc).expect("Tag not found");
+/// Escape text so it's displayed as is instead of being parsed as HTML
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
 
-    let content_placeholder = &content_caps["text"];
-    let sidebar_placholder = &sidebar_caps["text"];
+/// Lowercase id made of the letters and digits of any script, other characters become dashes
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() || c == '_' {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
 
-    let doc = doc.replace(content_placeholder, content);
-    doc.replace(sidebar_placholder, sidebar)
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
 }
 
 pub fn md_to_html(file: &str) -> String {
@@ -33,6 +52,35 @@ pub fn md_to_html(file: &str) -> String {
     parts.join(".")
 }
 
+/// Number of leading whitespace columns, tabs count as 4 columns
+pub fn indentation(line: &str) -> usize {
+    let mut columns = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => columns += 1,
+            '\t' => columns += 4 - columns % 4,
+            _ => break,
+        }
+    }
+    columns
+}
+
+/// Remove up to `columns` of leading whitespace from a line
+pub fn strip_indentation(line: &str, columns: usize) -> &str {
+    let mut removed = 0;
+    for (idx, c) in line.char_indices() {
+        if removed >= columns {
+            return &line[idx..];
+        }
+        match c {
+            ' ' => removed += 1,
+            '\t' => removed += 4 - removed % 4,
+            _ => return &line[idx..],
+        }
+    }
+    ""
+}
+
 /// Copy files from source to destination recursively.
 pub fn copy_recursively(source: impl AsRef<Path>, destination: impl AsRef<Path>) -> io::Result<()> {
     fs::create_dir_all(&destination)?;